@@ -0,0 +1,143 @@
+use std::{ops::Range, rc::Rc};
+
+/// Number of graphemes per chunk of `Boundaries`.
+const CHUNK_LEN: usize = 512;
+
+/// Char offset at which each grapheme of a row starts. The offsets are kept
+/// in chunks relative to the start of their chunk, so that an edit copies the
+/// chunks it touches and merely moves along the ones after it.
+#[derive(Debug, Default)]
+pub struct Boundaries {
+    chunks: Vec<Chunk>,
+    // Number of graphemes, and of chars.
+    len: usize,
+    chars: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Chunk {
+    // Index of the first grapheme in the chunk, and the char offset it starts at.
+    first: usize,
+    start: usize,
+    // Where each grapheme of the chunk starts, counted from `start`.
+    offsets: Rc<[usize]>,
+}
+
+impl Boundaries {
+    /// Boundaries of a row of `chars` chars whose graphemes start at `starts`.
+    pub fn new(starts: &[usize], chars: usize) -> Self {
+        Self {
+            chunks: Self::chunked(starts, 0),
+            len: starts.len(),
+            chars,
+        }
+    }
+
+    /// Number of graphemes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Char offset of the grapheme at `pos`, or of the end of the row past its last one.
+    pub fn char_at(&self, pos: usize) -> usize {
+        if pos >= self.len {
+            return self.chars;
+        }
+        let chunk = &self.chunks[self.chunk_of(pos)];
+        chunk.start + chunk.offsets[pos - chunk.first]
+    }
+
+    /// The grapheme which char offset `index` falls within, or the end of the
+    /// row past its last one.
+    pub fn position_of(&self, index: usize) -> usize {
+        if index >= self.chars {
+            return self.len;
+        }
+        let chunk = &self.chunks[self.chunks.partition_point(|chunk| chunk.start <= index) - 1];
+        let offset = index - chunk.start;
+        chunk.first + chunk.offsets.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Boundaries once the graphemes `replaced` were replaced by graphemes
+    /// starting at `starts`, leaving a row of `chars` chars.
+    pub fn splice(&self, replaced: Range<usize>, starts: &[usize], chars: usize) -> Self {
+        let Range { start, end } = replaced;
+        let moved = |pos: usize| pos + start + starts.len() - end;
+        let shifted = |offset: usize| offset + chars - self.chars;
+
+        // The chunks holding the replaced graphemes, if any, are split up anew.
+        let first = self.chunk_of(start);
+        let mut last = self
+            .chunks
+            .partition_point(|chunk| chunk.first < end)
+            .max(first + 1)
+            .min(self.chunks.len());
+        let mut spliced: Vec<usize> = self.starts(first..last, 0..start).collect();
+        spliced.extend_from_slice(starts);
+        spliced.extend(self.starts(first..last, end..self.len).map(shifted));
+        // Keep chunks from shrinking away edit by edit.
+        while spliced.len() < CHUNK_LEN / 2 && last < self.chunks.len() {
+            spliced.extend(self.starts(last..last + 1, 0..self.len).map(shifted));
+            last += 1;
+        }
+
+        let after = self.chunks[last..].iter().map(|chunk| Chunk {
+            first: moved(chunk.first),
+            start: shifted(chunk.start),
+            offsets: chunk.offsets.clone(),
+        });
+        let spliced = Self::chunked(
+            &spliced,
+            self.chunks.get(first).map_or(0, |chunk| chunk.first),
+        );
+        let chunks = self.chunks[..first]
+            .iter()
+            .cloned()
+            .chain(spliced)
+            .chain(after)
+            .collect();
+
+        Self {
+            chunks,
+            len: moved(self.len),
+            chars,
+        }
+    }
+
+    /// Chunk holding the grapheme at `pos`, or the last one past the end.
+    fn chunk_of(&self, pos: usize) -> usize {
+        self.chunks
+            .partition_point(|chunk| chunk.first <= pos)
+            .saturating_sub(1)
+    }
+
+    /// Char offsets of the graphemes in `chunks` within `graphemes`.
+    fn starts(
+        &self,
+        chunks: Range<usize>,
+        graphemes: Range<usize>,
+    ) -> impl Iterator<Item = usize> + '_ {
+        self.chunks[chunks].iter().flat_map(move |chunk| {
+            let graphemes = graphemes.clone();
+            chunk
+                .offsets
+                .iter()
+                .enumerate()
+                .filter(move |(index, _)| graphemes.contains(&(chunk.first + index)))
+                .map(|(_, offset)| chunk.start + offset)
+        })
+    }
+
+    /// `starts` split into chunks, the first of which holds grapheme `first`.
+    fn chunked(starts: &[usize], first: usize) -> Vec<Chunk> {
+        starts
+            .chunks(CHUNK_LEN)
+            .enumerate()
+            .map(|(index, chunk)| Chunk {
+                first: first + index * CHUNK_LEN,
+                start: chunk[0],
+                offsets: chunk.iter().map(|offset| offset - chunk[0]).collect(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    document::Document,
+    position::Position,
+    search::{Hit, MatchCounts, Query},
+};
+
+/// Minimum time between two writes of the swap file.
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
+/// A document open in the editor, along with where the user is in it.
+pub struct Buffer {
+    pub document: Document,
+    pub position: Position,
+    pub offset: Position,
+    pub search_query: Option<Query>,
+    pub search_counts: MatchCounts,
+    pub search_origin: Position,
+    pub search_hit: Option<Hit>,
+    /// Where the selection started; it extends from here to `position`.
+    pub anchor: Option<Position>,
+    /// Content of a swap file left behind by a crash, not yet offered for recovery.
+    pub recovery: Option<Box<Document>>,
+    // Set when the user chose to keep editing despite the file having changed.
+    pub disk_change_ignored: bool,
+    /// Something to tell about the buffer once it is switched to, such as how
+    /// opening it went.
+    pub message: Option<String>,
+    swap_revision: usize,
+    swap_time: Instant,
+}
+
+impl Buffer {
+    pub fn new(document: Document) -> Self {
+        Self {
+            recovery: document.open_swap().map(Box::new),
+            swap_revision: document.revision(),
+            document,
+            position: Position::zero(),
+            offset: Position::zero(),
+            search_query: None,
+            search_counts: MatchCounts::default(),
+            search_origin: Position::zero(),
+            search_hit: None,
+            anchor: None,
+            disk_change_ignored: false,
+            message: None,
+            swap_time: Instant::now(),
+        }
+    }
+
+    /// Ends the current undo step, to be undone back to the cursor.
+    pub fn checkpoint(&mut self) {
+        self.document.checkpoint(&self.position);
+    }
+
+    pub fn name(&self) -> &str {
+        self.document.filename.as_deref().unwrap_or("[New File]")
+    }
+
+    /// Start and end of the selected text, unless nothing is selected.
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.anchor.filter(|anchor| *anchor != self.position)?;
+        if (anchor.y, anchor.x) < (self.position.y, self.position.x) {
+            Some((anchor, self.position))
+        } else {
+            Some((self.position, anchor))
+        }
+    }
+
+    /// Writes unsaved changes to the swap file, at most once every `SWAP_INTERVAL`.
+    pub fn write_swap(&mut self) {
+        let revision = self.document.revision();
+        if revision == self.swap_revision || self.swap_time.elapsed() < SWAP_INTERVAL {
+            return;
+        }
+
+        // Undoing may lead back to the saved content, leaving nothing to recover.
+        if !self.document.is_dirty() {
+            self.document.remove_swap();
+            self.swap_revision = revision;
+            return;
+        }
+
+        self.swap_time = Instant::now();
+        if self.document.write_swap().is_ok() {
+            self.swap_revision = revision;
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use crate::{
+    config::{Config, LineNumbers},
+    position::Position,
+};
+
+pub const USAGE: &str = "\
+Usage: hecto [options] [+line] [file[:line[:column]] | -]...
+
+A file named - is read from standard input.
+
+Options:
+  --backup                 keep the previous content as file~ on every save
+  --osc52                  also copy to the system clipboard through the terminal
+  --tab-width=N            put tab stops every N columns (default 4)
+  --expand-tabs            have the Tab key insert spaces
+  --wrap                   wrap long lines onto several screen lines
+  --line-numbers           number lines
+  --relative-line-numbers  number lines by their distance from the cursor
+  --pipe                   write standard input, or the new file if no file is named,
+                           as last saved to standard output on quitting
+  -h, --help               show this help and exit
+  -V, --version            show the version and exit
+";
+
+/// Name standing for standard input in place of a file.
+const STDIN: &str = "-";
+
+/// A file named on the command line, with where to put the cursor in it.
+pub struct FileArg {
+    pub path: String,
+    pub position: Option<Position>,
+}
+
+impl FileArg {
+    /// Whether the file stands for standard input.
+    pub fn is_stdin(&self) -> bool {
+        self.path == STDIN
+    }
+}
+
+pub enum Command {
+    Edit { config: Config, files: Vec<FileArg> },
+    Help,
+    Version,
+}
+
+/// Reads the command line arguments, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut config = Config::default();
+    let mut files = vec![];
+    // Line given as `+line`, for the file which comes next.
+    let mut line = None;
+    let mut options_done = false;
+
+    for arg in args {
+        if options_done || arg == STDIN || !arg.starts_with(['-', '+']) {
+            let mut file = locate(&arg);
+            if let Some(line) = line.take() {
+                file.position = Some(Position::at(0, line));
+            }
+            files.push(file);
+            continue;
+        }
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--" => options_done = true,
+            "--backup" => config.backup = true,
+            "--osc52" => config.osc52 = true,
+            "--expand-tabs" => config.expand_tabs = true,
+            "--wrap" => config.wrap = true,
+            "--line-numbers" => config.line_numbers = LineNumbers::Absolute,
+            "--relative-line-numbers" => config.line_numbers = LineNumbers::Relative,
+            "--pipe" => config.pipe = true,
+            _ => {
+                if let Some(width) = arg.strip_prefix("--tab-width=") {
+                    config.tab_width = width
+                        .parse()
+                        .ok()
+                        .filter(|width| *width > 0)
+                        .ok_or_else(|| format!("invalid tab width: {width}"))?;
+                } else if let Some(number) = arg.strip_prefix('+') {
+                    line = Some(line_index(number).ok_or_else(|| format!("invalid line: {arg}"))?);
+                } else {
+                    return Err(format!("unknown option: {arg}"));
+                }
+            }
+        }
+    }
+
+    if line.is_some() {
+        return Err(String::from("a +line must come before a file"));
+    }
+    if files.iter().filter(|file| file.is_stdin()).count() > 1 {
+        return Err(String::from("standard input can only be read once"));
+    }
+    if config.pipe && !files.is_empty() && !files.iter().any(FileArg::is_stdin) {
+        return Err(String::from("--pipe needs - among the files"));
+    }
+    Ok(Command::Edit { config, files })
+}
+
+/// Splits a `:line` or `:line:column` suffix off `arg`, unless it names an
+/// existing file as it is.
+fn locate(arg: &str) -> FileArg {
+    let whole = || FileArg {
+        path: arg.to_string(),
+        position: None,
+    };
+    if arg == STDIN || Path::new(arg).exists() {
+        return whole();
+    }
+
+    let Some((rest, last)) = split_number(arg) else {
+        return whole();
+    };
+    match split_number(rest) {
+        Some((path, line)) if !path.is_empty() => FileArg {
+            path: path.to_string(),
+            position: Some(Position::at(last, line)),
+        },
+        _ if !rest.is_empty() => FileArg {
+            path: rest.to_string(),
+            position: Some(Position::at(0, last)),
+        },
+        _ => whole(),
+    }
+}
+
+/// Splits a trailing `:number` off `text`, with the number counted from zero.
+fn split_number(text: &str) -> Option<(&str, usize)> {
+    let (rest, number) = text.rsplit_once(':')?;
+    Some((rest, line_index(number)?))
+}
+
+/// Index of the line or column numbered `number`, counting from one.
+fn line_index(number: &str) -> Option<usize> {
+    number.parse::<usize>().ok()?.checked_sub(1)
+}
@@ -0,0 +1,47 @@
+/// Columns between tab stops, unless told otherwise.
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    /// Rows are numbered from the start of the document.
+    Absolute,
+    /// Rows are numbered by their distance from the cursor row, which keeps its own number.
+    Relative,
+}
+
+// Independent options, which read best as flags.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Keep the previous content of a file as `file~` when saving over it.
+    pub backup: bool,
+    /// Also copy to the system clipboard through the terminal, with OSC 52.
+    pub osc52: bool,
+    /// Columns between tab stops; never zero.
+    pub tab_width: usize,
+    /// The Tab key inserts spaces up to the next tab stop instead of a tab.
+    pub expand_tabs: bool,
+    /// Long rows are wrapped onto several screen lines instead of scrolling sideways.
+    pub wrap: bool,
+    pub line_numbers: LineNumbers,
+    /// Write the buffer read from standard input, or the new one when no file
+    /// is named, to standard output on quitting, as last saved, to edit text
+    /// passing through a shell pipeline.
+    pub pipe: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backup: false,
+            osc52: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            expand_tabs: false,
+            wrap: false,
+            line_numbers: LineNumbers::Off,
+            pipe: false,
+        }
+    }
+}
@@ -1,40 +1,127 @@
-use std::{fmt::Display, fs, io};
+use std::{
+    cell::RefCell,
+    cmp::min,
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
+    ops::Range,
+    path::Path,
+    rc::Rc,
+};
 
-use unicode_segmentation::UnicodeSegmentation;
+use ropey::{Rope, RopeBuilder, RopeSlice};
 
-use crate::{position::Position, row::Row, search::Hit};
+use crate::{
+    config::Config,
+    encoding::{hex_dump, Encoding},
+    file::{self, Stamp},
+    history::{Edit, History},
+    line_ending::LineEnding,
+    position::Position,
+    row::Row,
+    row_cache::RowCache,
+    search::{Hit, MatchCounts, Query},
+    swap,
+};
 
 #[derive(Debug)]
 pub enum OperationError {
     Position,
     EmptyFilename,
+    ReadOnly,
+    PermissionDenied,
+    ReadOnlyFilesystem,
+    DiskFull,
+    ChangedOnDisk,
+    Unencodable { position: Position },
     IO(io::Error),
 }
 
+impl From<io::Error> for OperationError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            return Self::PermissionDenied;
+        }
+        err.raw_os_error()
+            .and_then(Self::from_os_error)
+            .unwrap_or(Self::IO(err))
+    }
+}
+
+impl OperationError {
+    /// The errors `io::ErrorKind` only tells apart on recent toolchains.
+    #[cfg(unix)]
+    fn from_os_error(code: i32) -> Option<Self> {
+        match code {
+            libc::EROFS => Some(Self::ReadOnlyFilesystem),
+            libc::ENOSPC | libc::EDQUOT => Some(Self::DiskFull),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from_os_error(_code: i32) -> Option<Self> {
+        None
+    }
+}
+
 impl Display for OperationError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Position => write!(f, "Invalid position"),
             Self::EmptyFilename => write!(f, "Empty filename"),
+            Self::ReadOnly => write!(f, "Document is read-only"),
+            Self::PermissionDenied => write!(f, "Permission denied"),
+            Self::ReadOnlyFilesystem => write!(f, "Read-only file system"),
+            Self::DiskFull => write!(f, "No space left on device"),
+            Self::ChangedOnDisk => write!(f, "File changed on disk since it was read"),
+            Self::Unencodable { position } => write!(
+                f,
+                "Character at line {}, column {} cannot be written in this encoding",
+                position.y + 1,
+                position.x + 1
+            ),
             Self::IO(err) => write!(f, "IO error: {err}"),
         }
     }
 }
 
+/// Why a document cannot be edited.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReadOnly {
+    /// Shown as a hex dump, which must not be written back.
+    Binary,
+    /// Ends in the middle of a character, which saving would not keep.
+    Truncated,
+}
+
 #[derive(Default)]
 pub struct Document {
     pub filename: Option<String>,
-    rows: Vec<Row>,
+    pub config: Config,
+    // Every row, including the last one, is terminated by a `\n`.
+    text: Rope,
+    row_cache: RefCell<RowCache>,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    trailing_newline: bool,
+    read_only: Option<ReadOnly>,
+    // Changed in ways the history does not undo, such as the line ending.
     dirty: bool,
+    // Bumped on every change, so that unchanged content is not swapped out again.
+    revision: usize,
+    // The file as it was last read or written, to notice changes made by others.
+    stamp: Option<Stamp>,
+    history: History,
 }
 
-impl ToString for Document {
-    fn to_string(&self) -> String {
-        self.rows
-            .iter()
-            .map(ToString::to_string)
-            .collect::<Vec<_>>()
-            .join("\n")
+impl Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let content = self.text.slice(..self.text.len_chars().saturating_sub(1));
+        for chunk in content.chunks() {
+            write!(f, "{chunk}")?;
+        }
+        Ok(())
     }
 }
 
@@ -42,24 +129,92 @@ impl Document {
     pub fn new() -> Self {
         Self {
             filename: None,
-            rows: vec![],
+            config: Config::default(),
+            text: Rope::new(),
+            row_cache: RefCell::default(),
+            encoding: Encoding::default(),
+            line_ending: LineEnding::default(),
+            trailing_newline: true,
+            read_only: None,
             dirty: true,
+            revision: 0,
+            stamp: None,
+            history: History::new(),
         }
     }
 
     pub fn open(filename: &str) -> Result<Self, OperationError> {
-        let content = fs::read_to_string(filename).map_err(OperationError::IO)?;
+        let bytes = fs::read(filename)?;
+        let stamp = fs::metadata(filename).map(|metadata| Stamp::new(&metadata, &bytes));
+
         Ok(Self {
             filename: Some(filename.to_string()),
-            rows: content.lines().map(Row::from).collect(),
-            dirty: false,
+            stamp: stamp.ok(),
+            ..Self::decode(&bytes)
         })
     }
 
+    /// Reads an unnamed document, such as standard input, up to its end.
+    pub fn read(mut reader: impl Read) -> Result<Self, OperationError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Ok(Self::decode(&bytes))
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let (content, encoding, read_only) = match Encoding::detect(bytes) {
+            // Whatever is cut off would be lost on saving.
+            Some(encoding) if encoding.is_truncated(bytes) => {
+                (encoding.decode(bytes), encoding, Some(ReadOnly::Truncated))
+            }
+            Some(encoding) => (encoding.decode(bytes), encoding, None),
+            None => (hex_dump(bytes), Encoding::default(), Some(ReadOnly::Binary)),
+        };
+
+        let mut builder = RopeBuilder::new();
+        for line in content.lines() {
+            builder.append(line);
+            builder.append("\n");
+        }
+
+        Self {
+            filename: None,
+            config: Config::default(),
+            text: builder.finish(),
+            row_cache: RefCell::default(),
+            encoding,
+            line_ending: LineEnding::detect(&content),
+            trailing_newline: content.ends_with('\n'),
+            read_only,
+            dirty: false,
+            revision: 0,
+            stamp: None,
+            history: History::new(),
+        }
+    }
+
+    /// Saves the document, unless the file was changed by someone else since
+    /// it was read; see `overwrite`.
     pub fn save(&mut self) -> Result<(), OperationError> {
+        if self.changed_on_disk() {
+            return Err(OperationError::ChangedOnDisk);
+        }
+        self.overwrite()
+    }
+
+    /// Saves the document regardless of what happened to the file meanwhile.
+    pub fn overwrite(&mut self) -> Result<(), OperationError> {
+        if self.is_read_only() {
+            return Err(OperationError::ReadOnly);
+        }
+
         if let Some(filename) = &self.filename {
-            fs::write(filename, self.to_string()).map_err(OperationError::IO)?;
-            self.dirty = false;
+            self.check_encodable()?;
+            let path = Path::new(filename);
+            file::write_atomic(path, self.config.backup, None, |out| self.write_to(out))?;
+            self.stamp = Stamp::read(path).ok();
+            self.mark_saved();
+            self.remove_swap();
         } else {
             return Err(OperationError::EmptyFilename);
         }
@@ -67,105 +222,645 @@ impl Document {
         Ok(())
     }
 
-    pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+    /// Treats the content as saved, for documents which are written out elsewhere.
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+        self.history.mark_saved();
     }
 
-    pub fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
-        self.rows.get_mut(index)
+    /// Whether the file now holds something else than when it was last read or written.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let (Some(filename), Some(stamp)) = (&self.filename, &self.stamp) else {
+            return false;
+        };
+        // A file which went away cannot be clobbered by saving.
+        let Ok(metadata) = fs::metadata(filename) else {
+            return false;
+        };
+        if !stamp.is_stale(&metadata) {
+            return false;
+        }
+
+        match Stamp::read(Path::new(filename)) {
+            Ok(current) if current.same_content(stamp) => {
+                // Only touched; remember the new time to skip reading it next time.
+                self.stamp = Some(current);
+                false
+            }
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Replaces the content with that of the file, dropping any unsaved changes.
+    pub fn reload(&mut self) -> Result<(), OperationError> {
+        let filename = self
+            .filename
+            .as_ref()
+            .ok_or(OperationError::EmptyFilename)?;
+        let reloaded = Document::open(filename)?;
+
+        self.remove_swap();
+        *self = Document {
+            config: self.config,
+            revision: self.revision + 1,
+            ..reloaded
+        };
+        Ok(())
+    }
+
+    /// Writes the content out with the document's encoding and line endings.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let line_ending = self.line_ending.as_str();
+        out.write_all(self.encoding.bom())?;
+        for index in 0..self.height() {
+            if index > 0 {
+                self.encoding.encode(line_ending, out)?;
+            }
+            for chunk in self.line(index).chunks() {
+                self.encoding.encode(chunk, out)?;
+            }
+        }
+        if self.trailing_newline && !self.is_empty() {
+            self.encoding.encode(line_ending, out)?;
+        }
+        Ok(())
+    }
+
+    /// The content as `write_to` writes it, unless the encoding cannot represent
+    /// it or, as with `overwrite`, the document must not be written back.
+    pub fn encode(&self) -> Result<Vec<u8>, OperationError> {
+        if self.is_read_only() {
+            return Err(OperationError::ReadOnly);
+        }
+        self.check_encodable()?;
+        let mut bytes = vec![];
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Writes the unsaved content to the swap file kept next to the document.
+    pub fn write_swap(&self) -> Result<(), OperationError> {
+        let filename = self
+            .filename
+            .as_ref()
+            .ok_or(OperationError::EmptyFilename)?;
+        self.check_encodable()?;
+        let mode = Some(swap::mode(filename));
+        file::write_atomic(&swap::path(filename), false, mode, |out| self.write_to(out))?;
+        Ok(())
+    }
+
+    /// Fails on the first character the document's encoding cannot represent.
+    fn check_encodable(&self) -> Result<(), OperationError> {
+        for y in 0..self.height() {
+            let line = self.line(y);
+            if let Some(index) = self.encoding.unencodable(line.chars()) {
+                let x = self
+                    .row(y)
+                    .map_or(0, |row| row.to_position(line.char_to_byte(index)));
+                return Err(OperationError::Unencodable {
+                    position: Position::at(x, y),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_swap(&self) {
+        if let Some(filename) = &self.filename {
+            // A swap file which was never written is not an error.
+            let _ = fs::remove_file(swap::path(filename));
+        }
+    }
+
+    /// The content of a swap file left behind for this document, if it is newer.
+    pub fn open_swap(&self) -> Option<Document> {
+        let filename = self.filename.as_ref()?;
+        if self.is_read_only() || !swap::is_newer(filename) {
+            return None;
+        }
+        Document::open(swap::path(filename).to_str()?).ok()
+    }
+
+    /// Takes over the content of `swap`, keeping this document's file settings.
+    pub fn restore(&mut self, swap: Document) {
+        self.text = swap.text;
+        self.row_cache.borrow_mut().clear();
+        self.history = History::new();
+        self.dirty = true;
+        self.revision += 1;
+    }
+
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.is_some()
+    }
+
+    pub fn read_only(&self) -> Option<ReadOnly> {
+        self.read_only
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) -> Result<(), OperationError> {
+        if self.is_read_only() {
+            return Err(OperationError::ReadOnly);
+        }
+
+        if self.line_ending != line_ending {
+            self.line_ending = line_ending;
+            self.dirty = true;
+            self.revision += 1;
+        }
+        Ok(())
+    }
+
+    pub fn row(&self, index: usize) -> Option<Row<'_>> {
+        if index >= self.height() {
+            return None;
+        }
+
+        let line = self.line(index);
+        if Row::is_simple(line) {
+            return Some(Row::new(line, None, self.config.tab_width));
+        }
+
+        let mut cache = self.row_cache.borrow_mut();
+        let boundaries = cache.get(index).unwrap_or_else(|| {
+            let boundaries = Rc::new(Row::grapheme_boundaries(line));
+            cache.insert(index, boundaries.clone());
+            boundaries
+        });
+        Some(Row::new(line, Some(boundaries), self.config.tab_width))
     }
 
     pub fn width_at(&self, position: &Position) -> usize {
-        self.row(position.y).map(Row::len).unwrap_or_default()
+        self.row(position.y)
+            .map(|row| row.len())
+            .unwrap_or_default()
     }
 
     pub fn height(&self) -> usize {
-        self.rows.len()
+        self.text.len_lines() - 1
     }
 
-    pub fn translate(&self, position: &Position, offset: &Position) -> Position {
-        let raw_x = self
+    /// Screen cell of `position`, with the view scrolled to grapheme `offset`.
+    /// With rows wrapped at `wrap` columns, `offset` is a visual line as given
+    /// by `visual_line` instead, and must not be below `position`.
+    pub fn translate(
+        &self,
+        position: &Position,
+        offset: &Position,
+        wrap: Option<usize>,
+    ) -> Position {
+        if let Some(width) = wrap {
+            let line = self.visual_line(position, width);
+            // The end of a full line is shown on its last column.
+            let column = self
+                .column_in_line(position, width)
+                .min(width.saturating_sub(1));
+            return Position::at(column, self.lines_between(offset, &line, width));
+        }
+
+        let (raw_x, raw_offset_x) = self
             .row(position.y)
-            .map(|r| r.to_raw_position(position.x))
+            .map(|r| (r.to_raw_position(position.x), r.to_raw_position(offset.x)))
             .unwrap_or_default();
 
         let raw_y = position.y;
 
-        Position::at(raw_x, raw_y).diff(offset)
+        Position::at(raw_x, raw_y).diff(&Position::at(raw_offset_x, offset.y))
+    }
+
+    /// Grapheme at which each visual line of row `y` starts, wrapped at `width` columns.
+    pub fn line_starts(&self, y: usize, width: usize) -> Rc<[usize]> {
+        match self.row(y) {
+            Some(row) => self.wrap(y, &row, width),
+            None => Rc::new([0]),
+        }
     }
 
+    /// `row.wrap(width)` for row `y`, from the cache when wrapped at `width` before.
+    fn wrap(&self, y: usize, row: &Row, width: usize) -> Rc<[usize]> {
+        let mut cache = self.row_cache.borrow_mut();
+        cache.get_wrap(y, width).unwrap_or_else(|| {
+            let starts: Rc<[usize]> = row.wrap(width).into();
+            cache.insert_wrap(y, width, starts.clone());
+            starts
+        })
+    }
+
+    /// The visual line showing `position`, as the index of the line within
+    /// its row in `x` and the row in `y`.
+    pub fn visual_line(&self, position: &Position, width: usize) -> Position {
+        let starts = self.line_starts(position.y, width);
+        let line = starts.partition_point(|start| *start <= position.x) - 1;
+        Position::at(line, position.y)
+    }
+
+    /// Display column of `position` within its visual line.
+    pub fn column_in_line(&self, position: &Position, width: usize) -> usize {
+        let line = self.visual_line(position, width);
+        self.row(position.y).map_or(0, |row| {
+            let start = self.wrap(position.y, &row, width)[line.x];
+            row.to_raw_position(position.x) - row.to_raw_position(start)
+        })
+    }
+
+    /// The position shown at display `column` of visual `line`.
+    pub fn position_in_line(&self, line: &Position, column: usize, width: usize) -> Position {
+        let Some(row) = self.row(line.y) else {
+            return Position::at(0, line.y);
+        };
+        let starts = self.wrap(line.y, &row, width);
+        let index = line.x.min(starts.len() - 1);
+        let mut x = row.position_at_width(row.to_raw_position(starts[index]) + column);
+        // Past the end of a line that goes on below, stay on its last grapheme.
+        if let Some(next) = starts.get(index + 1) {
+            x = x.min(next - 1);
+        }
+        Position::at(x, line.y)
+    }
+
+    /// Number of visual lines from `from` down to `to`.
+    pub fn lines_between(&self, from: &Position, to: &Position, width: usize) -> usize {
+        if to.y <= from.y {
+            return to.x.saturating_sub(from.x);
+        }
+        let lines: usize = (from.y..to.y)
+            .map(|y| self.line_starts(y, width).len())
+            .sum();
+        lines + to.x - from.x
+    }
+
+    /// The visual line `lines` below `from`, or above it when negative,
+    /// stopping at the first and last lines of the document.
+    pub fn step_lines(&self, from: &Position, lines: isize, width: usize) -> Position {
+        let Position { x: mut line, mut y } = *from;
+        let mut remaining = lines.unsigned_abs();
+
+        if lines < 0 {
+            while remaining > line && y > 0 {
+                remaining -= line + 1;
+                y -= 1;
+                line = self.line_starts(y, width).len() - 1;
+            }
+            return Position::at(line.saturating_sub(remaining), y);
+        }
+        loop {
+            let last = self.line_starts(y, width).len() - 1;
+            if line + remaining <= last || y + 1 >= self.height() {
+                return Position::at((line + remaining).min(last), y);
+            }
+            remaining -= last - line + 1;
+            y += 1;
+            line = 0;
+        }
+    }
+
+    /// Inserts a typed char. Unlike other edits, a run of them is undone at once.
     pub fn insert_at(&mut self, position: &Position, c: char) -> Result<(), OperationError> {
-        if let Some(row) = self.row_mut(position.y) {
-            row.insert_at(position.x, c);
-            self.dirty = true;
-            return Ok(());
+        if self.is_read_only() {
+            return Err(OperationError::ReadOnly);
         }
 
-        Err(OperationError::Position)
+        let edit = Edit::Insert {
+            at: self.char_index(position).ok_or(OperationError::Position)?,
+            text: c.to_string(),
+        };
+        self.apply(&edit)?;
+        self.history.record(edit, true);
+        Ok(())
+    }
+
+    /// Inserts `text`, which may span several rows, as a single edit.
+    /// Returns the position just past the inserted text.
+    pub fn insert_text(
+        &mut self,
+        position: &Position,
+        text: &str,
+    ) -> Result<Position, OperationError> {
+        let at = self.char_index(position).ok_or(OperationError::Position)?;
+        if !text.is_empty() {
+            self.edit(Edit::Insert {
+                at,
+                text: text.to_string(),
+            })?;
+        }
+        Ok(self.position_after(at + text.chars().count()))
     }
 
     pub fn delete_at(&mut self, position: &Position) -> Result<(), OperationError> {
-        if let Some(row) = self.row_mut(position.y) {
-            if row.len() > 0 {
-                row.delete_at(position.x);
-                self.dirty = true;
-                return Ok(());
-            }
+        let text = self
+            .row(position.y)
+            .filter(|row| row.len() > 0)
+            .map(|row| row.render(position.x, position.x + 1))
+            .ok_or(OperationError::Position)?;
+
+        if text.is_empty() {
+            return Ok(());
         }
 
-        Err(OperationError::Position)
+        let at = self.char_index(position).ok_or(OperationError::Position)?;
+        self.edit(Edit::Delete { at, text })
     }
 
-    pub fn append_row(&mut self) {
-        self.rows.push(Row::new());
-        self.dirty = true;
+    pub fn append_row(&mut self) -> Result<(), OperationError> {
+        self.edit(Edit::AppendRow)
     }
 
     pub fn merge_row(&mut self, position: &Position) -> Result<(), OperationError> {
-        if let [prev, cur] = &mut self.rows[position.y.saturating_sub(1)..=position.y] {
-            prev.append(cur);
-            self.rows.remove(position.y);
-            self.dirty = true;
-            return Ok(());
+        if position.y == 0 || position.y >= self.height() {
+            return Err(OperationError::Position);
         }
 
-        Err(OperationError::Position)
+        // The line break which ends the row above.
+        let at = self.text.line_to_char(position.y) - 1;
+        self.edit(Edit::Merge { at })
     }
 
     pub fn split_row(&mut self, position: &Position) -> Result<(), OperationError> {
-        if let Some(row) = self.row_mut(position.y) {
-            let (left, right) = row.split_at(position.x);
-            self.rows[position.y] = left;
-            self.rows.insert(position.y + 1, right);
-            self.dirty = true;
+        let at = self.char_index(position).ok_or(OperationError::Position)?;
+        self.edit(Edit::Split { at })
+    }
+
+    /// Replaces the text highlighted by `hit`, which must lie within a single row.
+    pub fn replace(&mut self, hit: &Hit, text: &str) -> Result<(), OperationError> {
+        let (from, to) = hit.highlight;
+        let deleted = self
+            .row(from.y)
+            .filter(|_| from.y == to.y)
+            .map(|row| row.render(from.x, to.x))
+            .ok_or(OperationError::Position)?;
+        let at = self.char_index(&from).ok_or(OperationError::Position)?;
+
+        if !deleted.is_empty() {
+            self.edit(Edit::Delete { at, text: deleted })?;
+        }
+        if !text.is_empty() {
+            self.edit(Edit::Insert {
+                at,
+                text: text.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// `replacement` for `hit`, a match of `query`, with references to the
+    /// groups of a regex such as `$1` filled in.
+    pub fn expand(&self, query: &Query, hit: &Hit, replacement: &str) -> String {
+        let (from, _) = hit.highlight;
+        self.row(from.y).map_or_else(
+            || replacement.to_string(),
+            |row| row.expand(query, from.x, replacement),
+        )
+    }
+
+    /// Marks the boundary of an undo step, remembering `cursor` as the
+    /// position to restore when the step is undone.
+    pub fn checkpoint(&mut self, cursor: &Position) {
+        self.history.checkpoint(cursor);
+    }
+
+    /// Reverts the last undo step, returning the cursor position before it.
+    pub fn undo(&mut self) -> Option<Position> {
+        let step = self.history.undo_step()?;
+        let before = step.before;
+        let edits: Vec<Edit> = step.edits.iter().rev().map(Edit::inverse).collect();
+        self.apply_all(&edits).ok()?;
+        self.history.undo();
+        Some(before)
+    }
+
+    /// Reapplies the last undone step, returning the cursor position after it.
+    pub fn redo(&mut self) -> Option<Position> {
+        let step = self.history.redo_step()?;
+        let after = step.after;
+        let edits = step.edits.clone();
+        self.apply_all(&edits).ok()?;
+        self.history.redo();
+        Some(after)
+    }
+
+    /// Applies every one of `edits` or, failing that, none of them.
+    fn apply_all(&mut self, edits: &[Edit]) -> Result<(), OperationError> {
+        for (applied, edit) in edits.iter().enumerate() {
+            if let Err(e) = self.apply(edit) {
+                for edit in edits[..applied].iter().rev() {
+                    // Reverting an edit which was just applied cannot fail.
+                    let _ = self.apply(&edit.inverse());
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn edit(&mut self, edit: Edit) -> Result<(), OperationError> {
+        if self.is_read_only() {
+            return Err(OperationError::ReadOnly);
+        }
+
+        self.apply(&edit)?;
+        self.history.record(edit, false);
+        Ok(())
+    }
+
+    fn apply(&mut self, edit: &Edit) -> Result<(), OperationError> {
+        // Nothing goes past the line break which ends the last row.
+        let len = self.text.len_chars();
+        match edit {
+            Edit::Insert { at, text } => {
+                if *at >= len {
+                    return Err(OperationError::Position);
+                }
+                let y = self.text.char_to_line(*at);
+                self.text.insert(*at, text);
+                match text.matches('\n').count() {
+                    0 => self.update_row(y, *at, 0, text.chars().count()),
+                    breaks => self.splice_rows(y..y + 1, breaks + 1),
+                }
+            }
+            Edit::Delete { at, text } => {
+                let end = at + text.chars().count();
+                if end >= len {
+                    return Err(OperationError::Position);
+                }
+                let y = self.text.char_to_line(*at);
+                self.text.remove(*at..end);
+                match text.matches('\n').count() {
+                    0 => self.update_row(y, *at, end - at, 0),
+                    breaks => self.splice_rows(y..y + breaks + 1, 1),
+                }
+            }
+            Edit::Split { at } => {
+                if *at >= len {
+                    return Err(OperationError::Position);
+                }
+                let y = self.text.char_to_line(*at);
+                self.text.insert_char(*at, '\n');
+                self.splice_rows(y..y + 1, 2);
+            }
+            Edit::Merge { at } => {
+                if at + 1 >= len || self.text.char(*at) != '\n' {
+                    return Err(OperationError::Position);
+                }
+                let y = self.text.char_to_line(*at);
+                self.text.remove(*at..=*at);
+                self.splice_rows(y..y + 2, 1);
+            }
+            Edit::AppendRow => self.text.insert_char(self.text.len_chars(), '\n'),
+            Edit::RemoveLastRow => {
+                let last = self
+                    .height()
+                    .checked_sub(1)
+                    .ok_or(OperationError::Position)?;
+                let start = self.text.line_to_char(last);
+                self.text.remove(start..);
+                self.splice_rows(last..last + 1, 0);
+            }
+        }
+
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// The text from `from` up to `to`, including the line breaks in between.
+    pub fn text_range(&self, from: &Position, to: &Position) -> Option<String> {
+        let start = self.char_index(from)?;
+        let end = self.char_index(to)?;
+        (start <= end).then(|| self.text.slice(start..end).to_string())
+    }
+
+    /// Deletes the text from `from` up to `to`, which may span several rows, as a single edit.
+    pub fn delete_range(&mut self, from: &Position, to: &Position) -> Result<(), OperationError> {
+        let text = self.text_range(from, to).ok_or(OperationError::Position)?;
+
+        if text.is_empty() {
             return Ok(());
         }
 
-        Err(OperationError::Position)
+        let at = self.char_index(from).ok_or(OperationError::Position)?;
+        self.edit(Edit::Delete { at, text })
     }
 
-    pub fn search(&self, query: &str, after: &Position) -> Option<Hit> {
-        let query_len = query.graphemes(true).count();
+    /// Row `index` without its terminating line break.
+    fn line(&self, index: usize) -> RopeSlice<'_> {
+        let line = self.text.line(index);
+        line.slice(..line.len_chars().saturating_sub(1))
+    }
 
-        self.rows
-            .iter()
-            .skip(after.y)
-            .enumerate()
-            .find_map(|(y, row)| {
-                let after_x = if y == 0 { after.x } else { 0 };
-                row.search(query, after_x)
-                    .map(|x| Position::at(x, after.y + y))
-                    .map(|pos| {
-                        Hit::new(pos, pos.add(&Position::at(query_len, 0)))
-                    })
+    /// Char index into the text of the grapheme at `position`.
+    fn char_index(&self, position: &Position) -> Option<usize> {
+        self.row(position.y)
+            .map(|row| self.text.line_to_char(position.y) + row.char_at(position.x))
+    }
+
+    /// Position of the grapheme starting at char index `index` into the text,
+    /// or of the one after it if `index` falls within a grapheme.
+    fn position_after(&self, index: usize) -> Position {
+        let y = self.text.char_to_line(index);
+        let offset = index - self.text.line_to_char(y);
+        let x = self.row(y).map_or(0, |row| {
+            let x = row.to_position(self.line(y).char_to_byte(offset));
+            if row.char_at(x) < offset {
+                x + 1
+            } else {
+                x
+            }
+        });
+        Position::at(x, y)
+    }
+
+    /// Brings the cached graphemes of row `y` up to date, and drops its
+    /// wrapping, after `removed` chars at char index `index` were replaced
+    /// by `inserted` ones.
+    fn update_row(&self, y: usize, index: usize, removed: usize, inserted: usize) {
+        let mut cache = self.row_cache.borrow_mut();
+        let boundaries = cache.get(y);
+        // The row wraps differently now.
+        cache.remove(y);
+        let line = self.line(y);
+        if let (Some(boundaries), false) = (boundaries, Row::is_simple(line)) {
+            let at = index - self.text.line_to_char(y);
+            let boundaries = Row::update_boundaries(line, &boundaries, at, removed, inserted);
+            cache.insert(y, Rc::new(boundaries));
+        }
+    }
+
+    /// Drops what is cached about `rows`, which were replaced by `count`
+    /// rows, and moves those of the rows below along.
+    fn splice_rows(&self, rows: Range<usize>, count: usize) {
+        self.row_cache.borrow_mut().splice(rows, count);
+    }
+
+    pub fn search(&self, query: &Query, after: &Position) -> Option<Hit> {
+        (after.y..self.height()).find_map(|y| {
+            let after_x = if y == after.y { after.x } else { 0 };
+            self.row(y)
+                .and_then(|row| row.search(query, after_x))
+                .map(|(start, end)| Hit::new(Position::at(start, y), Position::at(end, y)))
+        })
+    }
+
+    pub fn rsearch(&self, query: &Query, before: &Position) -> Option<Hit> {
+        let last = min(before.y, self.height().checked_sub(1)?);
+
+        (0..=last).rev().find_map(|y| {
+            let before_x = if y == before.y { before.x } else { usize::MAX };
+            self.row(y)
+                .and_then(|row| row.rsearch(query, before_x))
+                .map(|(start, end)| Hit::new(Position::at(start, y), Position::at(end, y)))
+        })
+    }
+
+    /// Every hit of `query` within `rows`, in document order.
+    pub fn search_range(&self, query: &Query, rows: Range<usize>) -> Vec<Hit> {
+        rows.filter_map(|y| self.row(y).map(|row| (y, row)))
+            .flat_map(|(y, row)| {
+                row.search_all(query)
+                    .into_iter()
+                    .map(move |(start, end)| Hit::new(Position::at(start, y), Position::at(end, y)))
             })
+            .collect()
+    }
+
+    /// Counts the hits of `query` on every row, as far as `MatchCounts` goes.
+    pub fn count_matches(&self, query: &Query) -> MatchCounts {
+        MatchCounts::new((0..self.height()).map(|y| self.count_row_matches(query, y)))
+    }
+
+    /// Number of hits of `query` on row `y`.
+    pub fn count_row_matches(&self, query: &Query, y: usize) -> usize {
+        self.row(y).map_or(0, |row| row.search_all(query).len())
+    }
+
+    /// Number of hits of `query` before `hit` on its row.
+    pub fn matches_before(&self, query: &Query, hit: &Hit) -> usize {
+        let Position { x, y } = hit.position;
+        self.row(y).map_or(0, |row| {
+            row.search_all(query)
+                .iter()
+                .take_while(|(start, _)| *start < x)
+                .count()
+        })
     }
 
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.height() == 0
     }
 
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.dirty || !self.history.is_saved()
     }
 }
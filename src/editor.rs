@@ -1,15 +1,30 @@
 use std::{
-    io::{self, Stdout, Write},
-    time::Instant,
+    cmp,
+    io::{self, Write},
+    iter, mem,
+    time::{Duration, Instant},
 };
 
+use unicode_segmentation::UnicodeSegmentation;
+
 use crate::{
+    buffer::Buffer,
+    cli::FileArg,
+    clipboard::Clipboard,
     color::Color,
-    document::{Document, OperationError},
+    config::{Config, LineNumbers},
+    document::{Document, OperationError, ReadOnly},
+    encoding::Encoding,
+    highlight::{CommonSyntaxHighlighter, Highlight, Highlighter},
+    layout::{self, Direction, Layout, Pane, Rect, Split},
+    line_ending::LineEnding,
     position::Position,
     renderer::{render, RenderOutput},
-    search::Hit,
-    terminal::{Key, KeyCode, KeyModifiers, Terminal}, highlight::{Highlight, Highlighter, CommonSyntaxHighlighter},
+    search::{Hit, MatchCounts, Query, SearchOptions},
+    swap,
+    terminal::{
+        Input, Key, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind, Terminal,
+    },
 };
 
 type Error = io::Error;
@@ -17,9 +32,37 @@ type Result<T> = std::result::Result<T, Error>;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long to wait for a key before doing idle work such as writing the swap file.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Lines scrolled by one step of the mouse wheel.
+const WHEEL_SCROLL_LINES: isize = 3;
+
 enum EditorPrompt {
     Save,
     Search,
+    Replace,
+    ReplaceWith {
+        pattern: String,
+    },
+    ReplaceConfirm {
+        query: Query,
+        replacement: String,
+        replaced: usize,
+        /// Where the session started; it goes on from the top past the end, up to there.
+        origin: Position,
+        wrapped: bool,
+    },
+    /// Offers to restore the content of a swap file left behind by a crash.
+    Recover {
+        swap: Box<Document>,
+    },
+    /// Asks what to do about the file having been changed by another program.
+    FileChanged,
+    Open,
+    SwitchBuffer,
+    GotoLine,
+    /// Waits for the key of a command on panes, after ctrl-w.
+    Window,
 }
 
 enum EditorMode {
@@ -37,6 +80,16 @@ const STATUS_BG_COLOR: Color = Color::Rgb {
     g: 191,
     b: 191,
 };
+const STATUS_INACTIVE_BG_COLOR: Color = Color::Rgb {
+    r: 127,
+    g: 127,
+    b: 127,
+};
+const GUTTER_FG_COLOR: Color = Color::Rgb {
+    r: 127,
+    g: 127,
+    b: 127,
+};
 
 struct StatusMessage {
     text: String,
@@ -53,7 +106,7 @@ impl StatusMessage {
 
     fn help() -> Self {
         Self::new(String::from(
-            "help) ctrl-s: save | ctrl-f: search | ctrl-q: quit",
+            "help) ctrl-s: save | ctrl-o: open | ctrl-n/p: next/prev buffer | ctrl-b: buffers | ctrl-w: windows | ctrl-x/c/v: cut/copy/paste | ctrl-f: search | ctrl-r: replace | ctrl-g: go to line | ctrl-z: undo | ctrl-y: redo | ctrl-l: line endings | alt-z: wrap | alt-n: line numbers | ctrl-q: quit",
         ))
     }
 
@@ -61,18 +114,87 @@ impl StatusMessage {
         Self::new(String::from("help) Enter to save, Esc to cancel"))
     }
 
+    fn help_open() -> Self {
+        Self::new(String::from("help) Enter to open, Esc to cancel"))
+    }
+
+    fn help_goto() -> Self {
+        Self::new(String::from("help) Enter to go, Esc to cancel"))
+    }
+
     fn help_search() -> Self {
-        Self::new(String::from("help) Enter to search, Esc to cancel"))
+        Self::new(String::from(
+            "help) Enter to search, ctrl-r: regex, ctrl-e: case, ctrl-w: whole word, Esc to cancel",
+        ))
+    }
+
+    fn help_replace() -> Self {
+        Self::new(String::from(
+            "help) Enter to continue, ctrl-r: regex, ctrl-e: case, ctrl-w: whole word, Esc to cancel",
+        ))
+    }
+
+    fn help_replace_with() -> Self {
+        Self::new(String::from(
+            "help) Enter to start replacing, Esc to cancel",
+        ))
     }
 
-    fn warn_dirty() -> Self {
+    fn help_replace_confirm() -> Self {
         Self::new(String::from(
-            "Your changes will be lost if you quit now. Press Ctrl-Q again to quit.",
+            "help) y: replace | n: skip | a: replace all | q: quit",
+        ))
+    }
+
+    fn warn_dirty(count: usize) -> Self {
+        let buffers = if count == 1 {
+            String::from("a buffer")
+        } else {
+            format!("{count} buffers")
+        };
+        Self::new(format!(
+            "Your changes to {buffers} will be lost if you quit now. Press Ctrl-Q again to quit."
+        ))
+    }
+
+    fn open_file_error(filename: &str, e: &OperationError) -> Self {
+        Self::new(format!("Error opening file {filename}: {e}"))
+    }
+
+    fn read_stdin_error(e: &OperationError) -> Self {
+        Self::new(format!("Error reading standard input: {e}"))
+    }
+
+    fn new_file(filename: &str) -> Self {
+        Self::new(format!("New file: {filename}"))
+    }
+
+    fn binary_file() -> Self {
+        Self::new(String::from("Binary file shown as a read-only hex dump"))
+    }
+
+    fn truncated_file(encoding: Encoding) -> Self {
+        Self::new(format!(
+            "File ends in the middle of a {encoding} character; opened read-only"
         ))
     }
 
-    fn open_file_error(filename: &str) -> Self {
-        Self::new(format!("Error opening file: {filename}"))
+    /// Why a document just read cannot be edited.
+    fn opened_read_only(document: &Document) -> Self {
+        match document.read_only() {
+            Some(ReadOnly::Truncated) => Self::truncated_file(document.encoding()),
+            _ => Self::binary_file(),
+        }
+    }
+
+    fn read_only() -> Self {
+        Self::new(String::from("Document is read-only"))
+    }
+
+    fn save_pipe_ok() -> Self {
+        Self::new(String::from(
+            "Will be written to standard output on quitting",
+        ))
     }
 
     fn save_file_ok() -> Self {
@@ -83,12 +205,121 @@ impl StatusMessage {
         Self::new(format!("Error saving file: {e}"))
     }
 
+    fn line_ending_changed(line_ending: LineEnding) -> Self {
+        Self::new(format!("Line endings set to {line_ending}"))
+    }
+
+    fn nothing_to_undo() -> Self {
+        Self::new(String::from("Nothing to undo"))
+    }
+
+    fn nothing_to_redo() -> Self {
+        Self::new(String::from("Nothing to redo"))
+    }
+
+    fn wrap_toggled(wrap: bool) -> Self {
+        Self::new(String::from(if wrap {
+            "Soft wrap on"
+        } else {
+            "Soft wrap off"
+        }))
+    }
+
+    fn line_numbers(line_numbers: LineNumbers) -> Self {
+        Self::new(String::from(match line_numbers {
+            LineNumbers::Off => "Line numbers off",
+            LineNumbers::Absolute => "Line numbers on",
+            LineNumbers::Relative => "Relative line numbers on",
+        }))
+    }
+
+    fn clipboard_empty() -> Self {
+        Self::new(String::from("Nothing to paste"))
+    }
+
     fn no_search_results() -> Self {
         Self::new(String::from("No search results"))
     }
 
-    fn no_more_search_results() -> Self {
-        Self::new(String::from("No more search results"))
+    fn search_wrapped_to_top() -> Self {
+        Self::new(String::from("Search hit bottom, continuing at top"))
+    }
+
+    fn search_wrapped_to_bottom() -> Self {
+        Self::new(String::from("Search hit top, continuing at bottom"))
+    }
+
+    fn invalid_search_pattern(e: &regex::Error) -> Self {
+        // Syntax errors span several lines, pointing at the pattern; keep the gist.
+        let e = e.to_string();
+        let reason = e
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .trim_start_matches("error: ");
+        Self::new(format!("Invalid search pattern: {reason}"))
+    }
+
+    fn replaced(count: usize) -> Self {
+        let plural = if count == 1 { "" } else { "s" };
+        Self::new(format!("Replaced {count} occurrence{plural}"))
+    }
+
+    fn file_changed() -> Self {
+        Self::new(String::from(
+            "The file was changed by another program since it was read",
+        ))
+    }
+
+    fn file_reloaded() -> Self {
+        Self::new(String::from("File reloaded"))
+    }
+
+    fn file_changed_reloaded() -> Self {
+        Self::new(String::from("File changed on disk, reloaded"))
+    }
+
+    fn buffer_switched(index: usize, count: usize, name: &str, message: Option<&str>) -> Self {
+        match message {
+            Some(message) => Self::new(format!("Buffer {}/{count}: {name} | {message}", index + 1)),
+            None => Self::new(format!("Buffer {}/{count}: {name}", index + 1)),
+        }
+    }
+
+    fn buffer_list(names: &[String]) -> Self {
+        Self::new(names.join(" | "))
+    }
+
+    fn no_such_buffer(query: &str) -> Self {
+        Self::new(format!("No buffer matches {query}"))
+    }
+
+    fn invalid_line(input: &str) -> Self {
+        Self::new(format!("Not a line to go to: {input}"))
+    }
+
+    fn line_out_of_range(input: &str, height: usize) -> Self {
+        Self::new(format!("Line {input} is out of range (1-{height})"))
+    }
+
+    fn column_out_of_range(input: &str, width: usize) -> Self {
+        Self::new(format!("Column {input} is out of range (1-{width})"))
+    }
+
+    fn last_pane() -> Self {
+        Self::new(String::from("Cannot close the last pane"))
+    }
+
+    fn swap_found(summary: &str) -> Self {
+        Self::new(format!("Found a swap file with unsaved changes: {summary}"))
+    }
+
+    fn swap_recovered() -> Self {
+        Self::new(String::from("Recovered unsaved changes from the swap file"))
+    }
+
+    fn swap_discarded() -> Self {
+        Self::new(String::from("Discarded the swap file"))
     }
 
     fn is_recent(&self) -> bool {
@@ -97,56 +328,171 @@ impl StatusMessage {
 }
 
 pub struct Editor<'a> {
-    stdout: Stdout,
     terminal: &'a mut Terminal,
     mode: EditorMode,
-    document: Document,
-    position: Position,
-    offset: Position,
+    config: Config,
+    // Never empty; the buffer being edited is the one at `current`.
+    buffers: Vec<Buffer>,
+    current: usize,
+    // With `--pipe`, the buffer going down the pipeline: the one read from
+    // standard input, or else the unnamed one started with.
+    pipe: Option<usize>,
+    // What was last saved of the `pipe` buffer, to be written out on quitting.
+    piped: Option<Vec<u8>>,
+    layout: Layout,
+    panes: Vec<Pane>,
+    focus: usize,
     status_message: StatusMessage,
     prompt: String,
-    searched_hits: Vec<Hit>,
+    search_options: SearchOptions,
+    clipboard: Option<Clipboard>,
     quit: bool,
     quit_dirty: bool,
 }
 
 impl<'a> Editor<'a> {
-    pub fn new(terminal: &'a mut Terminal) -> Self {
+    pub fn new(terminal: &'a mut Terminal, config: Config) -> Self {
+        let mut document = Document::new();
+        document.config = config;
+
         Self {
-            stdout: io::stdout(),
             terminal,
             mode: EditorMode::Insert,
-            document: Document::new(),
-            position: Position::zero(),
-            offset: Position::zero(),
+            config,
+            buffers: vec![Buffer::new(document)],
+            current: 0,
+            pipe: config.pipe.then_some(0),
+            piped: None,
+            layout: Layout::Pane(0),
+            panes: vec![Pane::default()],
+            focus: 0,
             status_message: StatusMessage::help(),
             prompt: String::new(),
-            searched_hits: vec![],
+            search_options: SearchOptions::default(),
+            clipboard: None,
             quit: false,
             quit_dirty: false,
         }
     }
 
-    pub fn from_file(terminal: &'a mut Terminal, filename: &'a str) -> Self {
-        let mut status_message = StatusMessage::help();
-        let document = Document::open(filename).unwrap_or_else(|_| {
-            status_message = StatusMessage::open_file_error(filename);
-            Document::new()
-        });
+    /// Opens every file in `files` in a buffer of its own, starting with the first,
+    /// with the cursor where asked for, as far as the file goes. Files which
+    /// cannot be opened are left out.
+    /// Standard input, named `-`, has been read into `stdin` already.
+    pub fn from_files(
+        terminal: &'a mut Terminal,
+        config: Config,
+        files: &[FileArg],
+        mut stdin: Option<std::result::Result<Document, OperationError>>,
+    ) -> Self {
+        let mut editor = Self::new(terminal, config);
+        editor.pipe = None;
+        let mut buffers: Vec<Buffer> = vec![];
+        // The buffers with a position asked for, by index.
+        let mut positioned = vec![];
+        let mut errors = vec![];
+        for file in files {
+            let read = if file.is_stdin() { stdin.take() } else { None };
+            let opened = match read {
+                Some(read) => Self::stdin_document(config, read),
+                None => Self::open_document(config, &file.path),
+            };
+            match opened {
+                Ok((document, status_message)) => {
+                    if config.pipe && file.is_stdin() {
+                        editor.pipe = Some(buffers.len());
+                    }
+                    let mut buffer = Buffer::new(document);
+                    buffer.message = status_message.map(|status_message| status_message.text);
+                    if let Some(position) = file.position {
+                        buffer.position = position;
+                        positioned.push(buffers.len());
+                    }
+                    buffers.push(buffer);
+                }
+                Err(status_message) => errors.push(status_message.text),
+            }
+        }
 
-        Self {
-            stdout: io::stdout(),
-            terminal,
-            mode: EditorMode::Insert,
-            document,
-            position: Position::zero(),
-            offset: Position::zero(),
-            status_message,
-            prompt: String::new(),
-            searched_hits: vec![],
-            quit: false,
-            quit_dirty: false,
+        if !buffers.is_empty() {
+            editor.buffers = buffers;
+        }
+        for index in positioned {
+            editor.current = index;
+            editor.place_cursor();
+        }
+        editor.current = 0;
+
+        // Errors have no buffer to be told with, so they are told right away.
+        let first = editor.buffer_mut().message.take();
+        let messages: Vec<String> = errors.into_iter().chain(first).collect();
+        if !messages.is_empty() {
+            editor.status_message = StatusMessage::new(messages.join(" | "));
+        }
+        editor.offer_recovery();
+        editor
+    }
+
+    /// Brings the cursor of the current buffer, as asked for on the command
+    /// line, within its document and into the middle of the view.
+    fn place_cursor(&mut self) {
+        let line = self.buffer().position.y;
+        self.sanitize_position();
+        self.center();
+
+        let height = self.buffer().document.height().max(1);
+        if self.buffer().position.y != line {
+            let clamped = StatusMessage::line_out_of_range(&(line + 1).to_string(), height).text;
+            let buffer = self.buffer_mut();
+            buffer.message = Some(match buffer.message.take() {
+                Some(message) => format!("{message} | {clamped}"),
+                None => clamped,
+            });
+        }
+    }
+
+    /// The unnamed document `read` from standard input; keys still come from the terminal.
+    fn stdin_document(
+        config: Config,
+        read: std::result::Result<Document, OperationError>,
+    ) -> std::result::Result<(Document, Option<StatusMessage>), StatusMessage> {
+        let mut document = read.map_err(|e| StatusMessage::read_stdin_error(&e))?;
+        let status_message = document
+            .is_read_only()
+            .then(|| StatusMessage::opened_read_only(&document));
+        document.config = config;
+        Ok((document, status_message))
+    }
+
+    /// Opens `filename` for editing, along with anything worth telling about it.
+    fn open_document(
+        config: Config,
+        filename: &str,
+    ) -> std::result::Result<(Document, Option<StatusMessage>), StatusMessage> {
+        let (mut document, status_message) = match Document::open(filename) {
+            Ok(document) if document.is_read_only() => {
+                let status_message = StatusMessage::opened_read_only(&document);
+                (document, Some(status_message))
+            }
+            Ok(document) => (document, None),
+            Err(OperationError::IO(e)) if e.kind() == io::ErrorKind::NotFound => {
+                let mut document = Document::new();
+                document.filename = Some(filename.to_string());
+                (document, Some(StatusMessage::new_file(filename)))
+            }
+            Err(e) => return Err(StatusMessage::open_file_error(filename, &e)),
+        };
+        document.config = config;
+        Ok((document, status_message))
+    }
+
+    /// Writes what was saved of the piped buffer, if anything, for the rest
+    /// of a pipeline.
+    pub fn write_output(&self, out: &mut impl Write) -> io::Result<()> {
+        if let Some(piped) = &self.piped {
+            out.write_all(piped)?;
         }
+        out.flush()
     }
 
     pub fn run(&mut self) {
@@ -162,207 +508,704 @@ impl<'a> Editor<'a> {
             self.draw_window()?;
             self.draw_status_bar()?;
             self.draw_message_bar()?;
-            self.terminal
-                .move_cursor_to(&self.document.translate(&self.position, &self.offset))?;
-            self.terminal.show_cursor()?;
+            // The mouse wheel may have scrolled the cursor out of view.
+            if let Some(cursor) = self.cursor_on_screen() {
+                self.terminal.move_cursor_to(&cursor)?;
+                self.terminal.show_cursor()?;
+            }
 
             if self.quit {
+                for buffer in &mut self.buffers {
+                    buffer.document.remove_swap();
+                }
                 self.terminal.clear()?;
                 self.terminal.move_cursor_to(&Position::zero())?;
-                write!(self.stdout, "Goodbye! :)\r\n")?;
+                self.terminal.draw("Goodbye! :)\r\n", None, None)?;
                 break;
             }
 
-            let key = Terminal::read_key()?;
-
-            match self.mode {
-                EditorMode::Insert => {
-                    self.process_key(key);
+            let follow_cursor = match self.terminal.poll_input(IDLE_TIMEOUT)? {
+                Some(Input::Key(key)) => {
+                    self.dispatch_key(key);
+                    true
                 }
-                EditorMode::Prompt(_) => {
-                    self.process_prompt(key);
+                Some(Input::Mouse(event)) => self.process_mouse(event),
+                Some(Input::Paste(text)) => {
+                    self.paste(&text);
+                    true
                 }
-            }
+                Some(Input::Resize) => {
+                    self.resize()?;
+                    true
+                }
+                None => {
+                    self.check_disk();
+                    true
+                }
+            };
 
             self.sanitize_position();
-            self.scroll();
+            if follow_cursor {
+                self.scroll();
+            }
+            self.write_swap();
         }
         Ok(())
     }
 
-    fn draw_window(&mut self) -> Result<()> {
-        let window_width = self.window_width();
-        let window_height = self.window_height();
-        let Position {
-            x: offset_x,
-            y: offset_y,
-        } = self.offset;
-        let welcome_message_row = window_height / 3;
-
-        for row_idx in 0..window_height {
-            let row_idx = row_idx + offset_y;
+    fn dispatch_key(&mut self, key: Key) {
+        match self.mode {
+            EditorMode::Insert => {
+                self.buffer_mut().checkpoint();
+                self.process_key(key);
+            }
+            EditorMode::Prompt(EditorPrompt::ReplaceConfirm { .. }) => {
+                self.process_replace(key);
+            }
+            EditorMode::Prompt(EditorPrompt::Recover { .. }) => {
+                self.process_recover(key);
+            }
+            EditorMode::Prompt(EditorPrompt::FileChanged) => {
+                self.process_file_changed(key);
+            }
+            EditorMode::Prompt(EditorPrompt::Window) => {
+                self.process_window(key);
+            }
+            EditorMode::Prompt(_) => {
+                self.process_prompt(key);
+            }
+        }
+    }
 
-            self.terminal.clear_line()?;
+    fn paste(&mut self, text: &str) {
+        // Terminals send line breaks as carriage returns.
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
 
-            if let Some(row) = self.document.row(row_idx) {
-                let mut highlighters: Vec<Box<dyn Highlighter>> = vec![];
-                
-                if let EditorMode::Prompt(EditorPrompt::Search) = self.mode {
-                    highlighters.push(Box::new(SearchHitHighlighter::new(row_idx, self.searched_hits.clone())));
+        match &self.mode {
+            EditorMode::Insert if self.buffer().document.is_read_only() => {
+                self.status_message = StatusMessage::read_only();
+            }
+            EditorMode::Insert => {
+                // Keys are sent as control chars too; only line breaks and tabs are text.
+                let text: String = text
+                    .chars()
+                    .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+                    .collect();
+                self.buffer_mut().checkpoint();
+                self.delete_selection();
+                self.insert_text(&text);
+                self.buffer_mut().checkpoint();
+            }
+            EditorMode::Prompt(
+                EditorPrompt::Save
+                | EditorPrompt::Search
+                | EditorPrompt::Replace
+                | EditorPrompt::ReplaceWith { .. }
+                | EditorPrompt::Open
+                | EditorPrompt::SwitchBuffer
+                | EditorPrompt::GotoLine,
+            ) => {
+                let search = matches!(self.mode, EditorMode::Prompt(EditorPrompt::Search));
+                self.prompt.extend(text.chars().filter(|c| !c.is_control()));
+                if search {
+                    self.search_incremental();
                 }
+            }
+            EditorMode::Prompt(_) => {}
+        }
+    }
 
-                highlighters.push(Box::new(CommonSyntaxHighlighter::new()));
+    /// Inserts `text` at the cursor, leaving the cursor just past it.
+    fn insert_text(&mut self, text: &str) {
+        let buffer = self.buffer_mut();
+        let mut inserted = buffer.document.insert_text(&buffer.position, text);
+        if inserted.is_err() && buffer.document.append_row().is_ok() {
+            inserted = buffer.document.insert_text(&buffer.position, text);
+        }
+        if let Ok(end) = inserted {
+            buffer.position = end;
+        }
+    }
 
-                let line = row.render(0, row.len());
+    /// The selected text, or the cursor row when nothing is selected, along
+    /// with the range to delete when cutting it.
+    fn yank(&self) -> Option<(Clipboard, Position, Position)> {
+        let document = &self.buffer().document;
+        if let Some((from, to)) = self.buffer().selection() {
+            let text = document.text_range(&from, &to)?;
+            return Some((
+                Clipboard {
+                    text,
+                    linewise: false,
+                },
+                from,
+                to,
+            ));
+        }
 
-                let mut highlights = vec![];
-                for highlighter in highlighters {
-                    highlights.append(&mut highlighter.highlight(line.as_str()));
-                }
+        let y = self.buffer().position.y;
+        let end = Position::at(document.width_at(&Position::at(0, y)), y);
+        let mut text = document.text_range(&Position::at(0, y), &end)?;
+        text.push('\n');
+        // The last row has no line break of its own to take along, so the one
+        // before it goes instead.
+        let (from, to) = if y + 1 < document.height() {
+            (Position::at(0, y), Position::at(0, y + 1))
+        } else if y > 0 {
+            let prev = Position::at(0, y - 1);
+            (Position::at(document.width_at(&prev), y - 1), end)
+        } else {
+            (Position::at(0, y), end)
+        };
+        Some((
+            Clipboard {
+                text,
+                linewise: true,
+            },
+            from,
+            to,
+        ))
+    }
 
-                render(self.terminal, row, (offset_x, offset_x + window_width), &highlights)?;
-            } else if self.document.is_empty() && row_idx == welcome_message_row {
-                self.terminal.draw_line(
-                    Editor::welcome_message(window_width).as_str(),
-                    None,
-                    None,
-                )?;
+    fn copy(&mut self) {
+        if let Some((clipboard, _, _)) = self.yank() {
+            self.set_clipboard(clipboard);
+        }
+    }
+
+    fn cut(&mut self) {
+        if self.buffer().document.is_read_only() {
+            self.status_message = StatusMessage::read_only();
+            return;
+        }
+        let Some((clipboard, from, to)) = self.yank() else {
+            return;
+        };
+
+        let buffer = self.buffer_mut();
+        buffer.anchor = None;
+        if buffer.document.delete_range(&from, &to).is_ok() {
+            buffer.position = if clipboard.linewise {
+                Position::at(0, from.y)
             } else {
-                self.terminal
-                    .draw_line(Editor::empty_line().as_str(), None, None)?;
+                from
             };
+            self.set_clipboard(clipboard);
         }
-        Ok(())
     }
 
-    fn draw_status_bar(&mut self) -> Result<()> {
-        let status_bar_pos = Position::at(0, self.window_height());
-        self.terminal.move_cursor_to(&status_bar_pos)?;
+    fn set_clipboard(&mut self, clipboard: Clipboard) {
+        if self.config.osc52 {
+            // Terminals without OSC 52 ignore it; there is nothing to report.
+            let _ = self.terminal.set_clipboard(&clipboard.text);
+        }
+        self.clipboard = Some(clipboard);
+    }
 
-        let status_line = match &self.mode {
-            EditorMode::Insert => {
-                let mut filename = self
-                    .document
-                    .filename
-                    .clone()
-                    .unwrap_or_else(|| String::from("[New File]"));
-                filename.truncate(20);
-                let file_length = self.document.height();
-                let modified = if self.document.is_dirty() {
-                    "(modified)"
-                } else {
-                    ""
-                };
-                let file_status = format!("{filename} - {file_length} lines {modified}");
+    /// Pastes over the selection, or whole rows above the cursor row.
+    fn paste_clipboard(&mut self) {
+        let Some((text, linewise)) = self
+            .clipboard
+            .as_ref()
+            .map(|clipboard| (clipboard.text.clone(), clipboard.linewise))
+        else {
+            self.status_message = StatusMessage::clipboard_empty();
+            return;
+        };
+        if self.buffer().document.is_read_only() {
+            self.status_message = StatusMessage::read_only();
+            return;
+        }
 
-                let pos_status = format!("{}/{file_length}", self.position.y + 1);
+        if !self.delete_selection() && linewise {
+            self.buffer_mut().position.x = 0;
+        }
+        self.insert_text(&text);
+        self.buffer_mut().checkpoint();
+    }
 
-                // Align file_status to left, pos_status to right
-                let pad = " ".repeat(
-                    self.window_width()
-                        .saturating_sub(file_status.len() + pos_status.len()),
-                );
-                format!("{file_status}{pad}{pos_status}")
+    /// Handles a mouse event, returning whether the view should follow the cursor.
+    fn process_mouse(&mut self, event: MouseEvent) -> bool {
+        let (column, row) = (event.column as usize, event.row as usize);
+        let target = self.pane_rects().into_iter().find(|(_, rect)| {
+            (rect.x..rect.x + rect.width).contains(&column)
+                && (rect.y..rect.y + rect.height).contains(&row)
+        });
+
+        match (event.kind, target) {
+            (MouseEventKind::ScrollUp, Some((pane, rect))) => {
+                self.scroll_pane(pane, rect, -WHEEL_SCROLL_LINES);
+                return false;
             }
-            EditorMode::Prompt(EditorPrompt::Save) => {
-                let input = if self.prompt.is_empty() {
-                    "(enter filename)"
-                } else {
-                    &self.prompt[..]
-                };
-                let str = format!("Save as: {input}");
-                let pad = " ".repeat(self.window_width().saturating_sub(str.len()));
-                format!("{str}{pad}")
+            (MouseEventKind::ScrollDown, Some((pane, rect))) => {
+                self.scroll_pane(pane, rect, WHEEL_SCROLL_LINES);
+                return false;
             }
-            EditorMode::Prompt(EditorPrompt::Search) => {
-                let str = format!("Search: {}", self.prompt);
-                let pad = " ".repeat(self.window_width().saturating_sub(str.len()));
-                format!("{str}{pad}")
+            _ if !matches!(self.mode, EditorMode::Insert) => {}
+            // The status bar is not part of the text.
+            (MouseEventKind::Down(MouseButton::Left), Some((pane, rect)))
+                if row + 1 < rect.y + rect.height =>
+            {
+                self.focus_pane(pane);
+                let position = self.position_at(self.text_rect(pane, rect), column, row);
+                let buffer = self.buffer_mut();
+                // Close the step typed so far while the cursor is still where it ended.
+                buffer.checkpoint();
+                buffer.position = position;
+                buffer.anchor = Some(position);
             }
-        };
-
-        self.terminal.clear_line()?;
-        self.terminal.draw_line(
-            status_line.as_str(),
-            Some(STATUS_FG_COLOR),
-            Some(STATUS_BG_COLOR),
-        )?;
+            // Dragging on past the pane keeps extending the selection, scrolling along.
+            (MouseEventKind::Drag(MouseButton::Left), _) if self.buffer().anchor.is_some() => {
+                let rect = self.text_rect(self.focus, self.pane_rect());
+                self.buffer_mut().position = self.position_at(rect, column, row);
+            }
+            (MouseEventKind::Up(MouseButton::Left), _) if self.buffer().selection().is_none() => {
+                self.buffer_mut().anchor = None;
+            }
+            _ => {}
+        }
+        true
+    }
 
-        Ok(())
+    /// The position in the focused buffer shown at a screen cell of its pane,
+    /// `rect`. Cells past the pane map to lines beyond the ones on screen.
+    fn position_at(&self, rect: Rect, column: usize, row: usize) -> Position {
+        let offset = self.buffer().offset;
+        if self.config.wrap {
+            let document = &self.buffer().document;
+            let lines = isize::try_from(row.saturating_sub(rect.y)).unwrap_or(isize::MAX);
+            let line = document.step_lines(&offset, lines, rect.width);
+            return document.position_in_line(&line, column.saturating_sub(rect.x), rect.width);
+        }
+        let y = (offset.y + row)
+            .saturating_sub(rect.y)
+            .min(self.buffer().document.height().saturating_sub(1));
+        let x = self.buffer().document.row(y).map_or(0, |line| {
+            let start = line.to_raw_position(offset.x);
+            line.position_at_width(start + column.saturating_sub(rect.x))
+        });
+        Position::at(x, y)
     }
 
-    fn draw_message_bar(&mut self) -> Result<()> {
-        let message_bar_pos = Position::at(0, self.window_height() + 1);
-        self.terminal.move_cursor_to(&message_bar_pos)?;
+    /// Scrolls the view of `pane` by `lines`, leaving its cursor where it is.
+    fn scroll_pane(&mut self, pane: usize, rect: Rect, lines: isize) {
+        let (document, mut offset) = if pane == self.focus {
+            (&self.buffer().document, self.buffer().offset)
+        } else {
+            let pane = &self.panes[pane];
+            (&self.buffers[pane.buffer].document, pane.offset)
+        };
+        let window_height = rect.height.saturating_sub(1);
+
+        // Keep at least a screen's worth of the document in view, when there is one.
+        if self.config.wrap {
+            let width = self.text_rect(pane, rect).width;
+            let last_row = document.height().saturating_sub(1);
+            let end = Position::at(document.line_starts(last_row, width).len() - 1, last_row);
+            let last = document.step_lines(&end, lines_up(window_height), width);
+            let limit = cmp::max_by_key(last, offset, |line| (line.y, line.x));
+            let stepped = document.step_lines(&offset, lines, width);
+            offset = cmp::min_by_key(stepped, limit, |line| (line.y, line.x));
+        } else {
+            let last = document.height().saturating_sub(window_height);
+            offset.y = offset
+                .y
+                .saturating_add_signed(lines)
+                .min(last.max(offset.y));
+        }
 
-        self.terminal.clear_line()?;
-        if self.status_message.is_recent() {
-            self.terminal
-                .draw_line(self.status_message.text.as_str(), None, None)?;
+        if pane == self.focus {
+            self.buffer_mut().offset = offset;
+        } else {
+            self.panes[pane].offset = offset;
         }
+    }
 
-        Ok(())
+    /// Where the cursor goes on screen, if it is within the focused pane.
+    fn cursor_on_screen(&self) -> Option<Position> {
+        let Buffer {
+            position, offset, ..
+        } = self.buffer();
+        let document = &self.buffer().document;
+        let (width, height) = (self.window_width(), self.window_height());
+        if !in_view(document, position, offset, width, height, self.config.wrap) {
+            return None;
+        }
+
+        let rect = self.text_rect(self.focus, self.pane_rect());
+        let cursor = document.translate(position, offset, self.config.wrap.then_some(width));
+        Some(cursor.add(&Position::at(rect.x, rect.y)))
     }
 
-    fn empty_line() -> String {
-        String::from("~\r")
+    /// Starts over on a screen of a new size, keeping every cursor in view.
+    fn resize(&mut self) -> Result<()> {
+        for (pane, rect) in self.pane_rects() {
+            if pane != self.focus {
+                let Pane {
+                    buffer,
+                    position,
+                    offset,
+                } = self.panes[pane];
+                self.panes[pane].offset = follow(
+                    &self.buffers[buffer].document,
+                    &position,
+                    offset,
+                    self.text_rect(pane, rect).width,
+                    rect.height.saturating_sub(1),
+                    self.config.wrap,
+                );
+            }
+        }
+        // The focused pane is scrolled along with every other input.
+        self.terminal.clear()
     }
 
-    fn welcome_message(width: usize) -> String {
-        let msg = format!("Hecto editor -- version {VERSION}");
-        let len = msg.len();
-        let padding = width.saturating_sub(len) / 2;
-        let pad = " ".repeat(padding.saturating_sub(1));
-        format!("~{pad}{}\r", &msg[..len])
+    fn draw_window(&mut self) -> Result<()> {
+        for (pane, rect) in self.pane_rects() {
+            let text_rect = self.text_rect(pane, rect);
+            let gutter_width = text_rect.x - rect.x;
+            let lines = self.pane_lines(pane, text_rect);
+            for (y, (row, line)) in lines.iter().enumerate() {
+                self.terminal
+                    .move_cursor_to(&Position::at(rect.x, rect.y + y))?;
+                if gutter_width > 0 {
+                    let width = gutter_width - 1;
+                    // Lines past the end and continuations of a wrapped row go without.
+                    let number = row
+                        .map(|row| self.line_number(pane, row).to_string())
+                        .unwrap_or_default();
+                    // In a pane too narrow for the whole number, keep its last digits.
+                    let number = &number[number.len().saturating_sub(width)..];
+                    let gutter = format!("{number:>width$} ");
+                    self.terminal.draw(&gutter, Some(GUTTER_FG_COLOR), None)?;
+                }
+                self.terminal.draw(line, None, None)?;
+            }
+        }
+
+        for separator in self.layout.separators(self.area()) {
+            for y in separator.y..separator.y + separator.height {
+                self.terminal
+                    .move_cursor_to(&Position::at(separator.x, y))?;
+                self.terminal.draw("│", Some(STATUS_BG_COLOR), None)?;
+            }
+        }
+        Ok(())
     }
 
-    fn process_key(&mut self, key: Key) {
+    /// The text lines of `pane`, each with the row it starts, if any.
+    fn pane_lines(&self, pane: usize, rect: Rect) -> Vec<(Option<usize>, String)> {
+        let focused = pane == self.focus;
+        let (buffer, offset) = if focused {
+            (self.buffer(), self.buffer().offset)
+        } else {
+            let pane = &self.panes[pane];
+            (&self.buffers[pane.buffer], pane.offset)
+        };
+        let window_width = rect.width;
+        let window_height = rect.height.saturating_sub(1);
         let Position {
-            x: mut position_x,
-            y: mut position_y,
-        } = self.position;
+            x: offset_x,
+            y: offset_y,
+        } = offset;
+        let welcome_message_row = window_height / 3;
 
-        match key {
-            // In most cases we will use ctrl+q for quitting,
-            // but apparently VSCode skips sending ctrl+q to the terminal.
-            (_, KeyCode::Char('q')) => self.try_quit(),
-            (_, KeyCode::Left) => {
-                if position_x > 0 {
-                    position_x -= 1;
-                } else if position_y > 0 {
-                    position_y -= 1;
-                    position_x = self.document.width_at(&Position::at(0, position_y));
-                }
+        // Search results are only shown where the search is going on.
+        let query = match &self.mode {
+            EditorMode::Prompt(EditorPrompt::Search) if focused => buffer.search_query.as_ref(),
+            EditorMode::Prompt(EditorPrompt::ReplaceConfirm { query, .. }) if focused => {
+                Some(query)
             }
-            (_, KeyCode::Right) => {
-                if position_x < self.document.width_at(&self.position) {
-                    position_x += 1;
-                } else if position_y < self.document.height().saturating_sub(1) {
-                    position_y += 1;
-                    position_x = 0;
+            _ => None,
+        };
+        let visible_hits = query
+            .map(|query| {
+                buffer
+                    .document
+                    .search_range(query, offset_y..offset_y + window_height)
+            })
+            .unwrap_or_default();
+
+        let mut lines = vec![];
+        let mut row_idx = offset_y;
+        while lines.len() < window_height {
+            if let Some(row) = buffer.document.row(row_idx) {
+                // Grapheme ranges of the visual lines shown of the row.
+                let shown: Vec<(usize, usize)> = if self.config.wrap {
+                    // With soft wrap, `offset_x` counts lines into the first row.
+                    let starts = buffer.document.line_starts(row_idx, window_width);
+                    let ends = starts.iter().skip(1).copied().chain(iter::once(row.len()));
+                    let skipped = if row_idx == offset_y { offset_x } else { 0 };
+                    starts
+                        .iter()
+                        .copied()
+                        .zip(ends)
+                        .skip(skipped)
+                        .take(window_height - lines.len())
+                        .collect()
+                } else {
+                    vec![(offset_x, row.fit_width(offset_x, window_width))]
+                };
+                let start = shown.first().map_or(0, |(start, _)| *start);
+                let end = shown.last().map_or(0, |(_, end)| *end);
+
+                let mut highlighters: Vec<Box<dyn Highlighter>> = vec![];
+
+                if let Some(selection) = buffer.selection().filter(|_| focused) {
+                    highlighters.push(Box::new(SelectionHighlighter::new(row_idx, selection)));
                 }
+
+                if query.is_some() {
+                    highlighters.push(Box::new(SearchHitHighlighter::new(
+                        row_idx,
+                        visible_hits.clone(),
+                        buffer.search_hit,
+                    )));
+                }
+
+                highlighters.push(Box::new(CommonSyntaxHighlighter::new()));
+
+                // Only what is on screen is rendered and highlighted.
+                let line = row.render(start, end);
+
+                let mut highlights = vec![];
+                for highlighter in highlighters {
+                    highlights.append(&mut highlighter.highlight(line.as_str(), start));
+                }
+
+                for (start, end) in shown {
+                    // Continuation lines of a wrapped row are not numbered.
+                    let first = start == 0 || !self.config.wrap;
+                    lines.push((
+                        first.then_some(row_idx),
+                        render::<Terminal>(&row, start, end, window_width, &highlights),
+                    ));
+                }
+                row_idx += 1;
+            } else if buffer.document.is_empty() && lines.len() == welcome_message_row {
+                let welcome_message = Editor::welcome_message(window_width);
+                lines.push((None, fit(&welcome_message, window_width)));
+            } else {
+                lines.push((None, fit(&Editor::empty_line(), window_width)));
             }
-            (_, KeyCode::Up) => {
-                position_y = position_y.saturating_sub(1);
-            }
-            (_, KeyCode::Down) => {
-                position_y += 1;
-            }
-            (_, KeyCode::Home) => {
-                position_x = 0;
-            }
-            (_, KeyCode::End) => {
-                position_x = self.document.width_at(&self.position);
-            }
-            (_, KeyCode::PageUp) => {
-                position_y = position_y.saturating_sub(self.window_height());
-            }
-            (_, KeyCode::PageDown) => {
-                position_y += self.window_height();
+        }
+        lines
+    }
+
+    fn draw_status_bar(&mut self) -> Result<()> {
+        for (pane, rect) in self.pane_rects() {
+            let (status, right_status, bg_color) = if pane == self.focus {
+                let (status, right_status) = self.status();
+                (status, right_status, STATUS_BG_COLOR)
+            } else {
+                let pane = &self.panes[pane];
+                let (status, right_status) =
+                    self.file_status(pane.buffer, &self.buffers[pane.buffer], &pane.position);
+                (status, right_status, STATUS_INACTIVE_BG_COLOR)
+            };
+
+            let pad = " ".repeat(
+                rect.width
+                    .saturating_sub(status.chars().count() + right_status.chars().count()),
+            );
+            let status_line = fit(&format!("{status}{pad}{right_status}"), rect.width);
+
+            let status_bar_pos = Position::at(rect.x, rect.y + rect.height.saturating_sub(1));
+            self.terminal.move_cursor_to(&status_bar_pos)?;
+            self.terminal
+                .draw(&status_line, Some(STATUS_FG_COLOR), Some(bg_color))?;
+        }
+
+        Ok(())
+    }
+
+    /// Left and right aligned parts of the status line of the focused pane.
+    fn status(&self) -> (String, String) {
+        match &self.mode {
+            EditorMode::Insert => self.file_status(self.current, self.buffer(), &self.buffer().position),
+            EditorMode::Prompt(EditorPrompt::Save) => (
+                format!("Save as: {}", self.prompt_input("(enter filename)")),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::Search) => (
+                format!("Search{}: {}", self.search_flags(), self.prompt),
+                self.match_status(self.buffer().search_query.as_ref()),
+            ),
+            EditorMode::Prompt(EditorPrompt::Replace) => (
+                format!("Replace{}: {}", self.search_flags(), self.prompt),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::ReplaceWith { pattern }) => (
+                format!("Replace {pattern} with: {}", self.prompt),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::ReplaceConfirm {
+                query, replacement, ..
+            }) => (
+                format!("Replace with {replacement}? (y)es (n)o (a)ll (q)uit"),
+                self.match_status(Some(query)),
+            ),
+            EditorMode::Prompt(EditorPrompt::Recover { .. }) => (
+                String::from("Recover unsaved changes from the swap file? (y)es (n)o"),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::FileChanged) => (
+                String::from("File changed on disk: (r)eload (o)verwrite (k)eep editing"),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::Open) => (
+                format!("Open: {}", self.prompt_input("(enter path)")),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::Window) => (
+                String::from(
+                    "Window: (s)plit (v)ertical split | arrows: focus | (w) next | +-<>: resize | (c)lose",
+                ),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::GotoLine) => (
+                format!(
+                    "Go to line: {}",
+                    self.prompt_input("(line[:column], +lines, -lines or %percent)")
+                ),
+                String::new(),
+            ),
+            EditorMode::Prompt(EditorPrompt::SwitchBuffer) => (
+                format!(
+                    "Switch to buffer: {}",
+                    self.prompt_input("(enter number or name)")
+                ),
+                String::new(),
+            ),
+        }
+    }
+
+    fn file_status(&self, index: usize, buffer: &Buffer, position: &Position) -> (String, String) {
+        let mut filename = buffer.name().to_string();
+        filename.truncate(20);
+        let file_length = buffer.document.height();
+        let modified = if buffer.document.is_read_only() {
+            "(read-only)"
+        } else if buffer.document.is_dirty() {
+            "(modified)"
+        } else {
+            ""
+        };
+        let number = if self.buffer_count() > 1 {
+            format!("[{}/{}] ", index + 1, self.buffer_count())
+        } else {
+            String::new()
+        };
+        let file_status = format!("{number}{filename} - {file_length} lines {modified}");
+
+        let pos_status = format!(
+            "{} {} {}/{file_length}",
+            buffer.document.encoding(),
+            buffer.document.line_ending(),
+            position.y + 1
+        );
+        (file_status, pos_status)
+    }
+
+    fn prompt_input<'s>(&'s self, placeholder: &'s str) -> &'s str {
+        if self.prompt.is_empty() {
+            placeholder
+        } else {
+            &self.prompt
+        }
+    }
+
+    fn search_flags(&self) -> String {
+        let flags = self.search_options.to_string();
+        if flags.is_empty() {
+            flags
+        } else {
+            format!(" ({flags})")
+        }
+    }
+
+    fn match_status(&self, query: Option<&Query>) -> String {
+        match (query, &self.buffer().search_hit) {
+            (Some(query), Some(hit)) => self.buffer().search_counts.describe(
+                hit.position.y,
+                self.buffer().document.matches_before(query, hit),
+            ),
+            _ => String::new(),
+        }
+    }
+
+    fn draw_message_bar(&mut self) -> Result<()> {
+        let message_bar_pos =
+            Position::at(0, (self.terminal.size().height as usize).saturating_sub(1));
+        self.terminal.move_cursor_to(&message_bar_pos)?;
+
+        self.terminal.clear_line()?;
+        // The recovery prompt relies on the message bar to explain itself.
+        let explaining = matches!(
+            self.mode,
+            EditorMode::Prompt(
+                EditorPrompt::Recover { .. }
+                    | EditorPrompt::FileChanged
+                    | EditorPrompt::SwitchBuffer
+            )
+        );
+        if explaining || self.status_message.is_recent() {
+            self.terminal
+                .draw_line(self.status_message.text.as_str(), None, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn empty_line() -> String {
+        String::from("~")
+    }
+
+    fn welcome_message(width: usize) -> String {
+        let msg = format!("Hecto editor -- version {VERSION}");
+        let len = msg.len();
+        let padding = width.saturating_sub(len) / 2;
+        let pad = " ".repeat(padding.saturating_sub(1));
+        format!("~{pad}{}", &msg[..len])
+    }
+
+    fn process_key(&mut self, key: Key) {
+        if self.replace_selection(key) {
+            return;
+        }
+
+        let position = self.buffer().position;
+        let Position {
+            x: mut position_x,
+            y: mut position_y,
+        } = position;
+
+        match key {
+            // In most cases we will use ctrl+q for quitting,
+            // but apparently VSCode skips sending ctrl+q to the terminal.
+            (_, KeyCode::Char('q')) => self.try_quit(),
+            (
+                _,
+                KeyCode::Left
+                | KeyCode::Right
+                | KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Home
+                | KeyCode::End
+                | KeyCode::PageUp
+                | KeyCode::PageDown,
+            ) => return self.navigate(key),
+            (_, KeyCode::Esc) => self.buffer_mut().anchor = None,
+            (_, KeyCode::Backspace | KeyCode::Delete | KeyCode::Enter | KeyCode::Tab)
+            | (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(_))
+                if self.buffer().document.is_read_only() =>
+            {
+                self.status_message = StatusMessage::read_only();
             }
             (_, KeyCode::Backspace) => {
                 if position_x > 0 {
                     if self
+                        .buffer_mut()
                         .document
                         .delete_at(&Position::at(position_x - 1, position_y))
                         .is_ok()
@@ -371,18 +1214,20 @@ impl<'a> Editor<'a> {
                     }
                 } else {
                     let prev_width = self
+                        .buffer()
                         .document
                         .width_at(&Position::at(0, position_y.saturating_sub(1)));
-                    if self.document.merge_row(&self.position).is_ok() {
+                    if self.buffer_mut().document.merge_row(&position).is_ok() {
                         position_x = prev_width;
                         position_y -= 1;
                     }
                 }
             }
             (_, KeyCode::Delete) => {
-                if position_x < self.document.width_at(&self.position) {
-                    self.document.delete_at(&self.position).unwrap();
+                if position_x < self.buffer().document.width_at(&position) {
+                    self.buffer_mut().document.delete_at(&position).unwrap();
                 } else if self
+                    .buffer_mut()
                     .document
                     .merge_row(&Position::at(0, position_y + 1))
                     .is_ok()
@@ -390,76 +1235,202 @@ impl<'a> Editor<'a> {
                 }
             }
             (_, KeyCode::Enter) => {
-                if self.document.split_row(&self.position).is_ok() {
+                if self.buffer_mut().document.split_row(&position).is_ok() {
                     position_x = 0;
                     position_y += 1;
-                } else {
-                    self.document.append_row();
+                } else if self.buffer_mut().document.append_row().is_ok() {
                     return self.process_key(key);
                 }
             }
-            (KeyModifiers::CONTROL, KeyCode::Char('f')) => self.search_prompt(),
-            (KeyModifiers::CONTROL, KeyCode::Char('s')) => self.save_document(),
+            (KeyModifiers::CONTROL, KeyCode::Char(c)) => return self.process_command(c),
+            (KeyModifiers::ALT, KeyCode::Char('z')) => self.toggle_wrap(),
+            (KeyModifiers::ALT, KeyCode::Char('n')) => self.toggle_line_numbers(),
+            (_, KeyCode::Tab) => return self.insert_tab(),
             (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
-                if self.document.insert_at(&self.position, c).is_ok() {
+                return self.type_char(c);
+            }
+            _ => {}
+        }
+
+        self.buffer_mut().position = Position::at(position_x, position_y);
+    }
+
+    /// Inserts `c` at the cursor, as part of a run of typed characters.
+    fn type_char(&mut self, c: char) {
+        let buffer = self.buffer_mut();
+        let position = buffer.position;
+        let document = &mut buffer.document;
+        if document.insert_at(&position, c).is_ok()
+            || (document.append_row().is_ok() && document.insert_at(&position, c).is_ok())
+        {
+            buffer.position.x += 1;
+        }
+    }
+
+    /// Inserts a tab, or spaces up to the next tab stop with `expand_tabs`.
+    fn insert_tab(&mut self) {
+        if !self.config.expand_tabs {
+            return self.type_char('\t');
+        }
+        let tab_width = self.config.tab_width;
+        let column = self
+            .buffer()
+            .document
+            .row(self.buffer().position.y)
+            .map_or(0, |row| row.to_raw_position(self.buffer().position.x));
+        self.insert_text(&" ".repeat(tab_width - column % tab_width));
+    }
+
+    fn process_command(&mut self, c: char) {
+        match c {
+            'f' => self.search_prompt(),
+            'r' => self.replace_prompt(),
+            's' => self.save_document(),
+            'z' => self.undo(),
+            'y' => self.redo(),
+            'l' => self.toggle_line_ending(),
+            'x' => self.cut(),
+            'c' => self.copy(),
+            'v' => self.paste_clipboard(),
+            'o' => self.open_prompt(),
+            'b' => self.buffer_prompt(),
+            'g' => self.goto_prompt(),
+            'w' => self.mode = EditorMode::Prompt(EditorPrompt::Window),
+            'n' => self.switch_buffer((self.current + 1) % self.buffer_count()),
+            'p' => {
+                let count = self.buffer_count();
+                self.switch_buffer((self.current + count - 1) % count);
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the cursor; with shift held, the selection is extended along.
+    fn navigate(&mut self, key: Key) {
+        if key.0.contains(KeyModifiers::SHIFT) {
+            let buffer = self.buffer_mut();
+            buffer.anchor.get_or_insert(buffer.position);
+        } else {
+            self.buffer_mut().anchor = None;
+        }
+        self.move_cursor(key);
+    }
+
+    /// Moves the cursor up or down by visual lines, keeping its column.
+    fn move_visual(&mut self, key: Key) {
+        let width = self.window_width();
+        let page = isize::try_from(self.window_height()).unwrap_or(isize::MAX);
+        let lines = match key.1 {
+            KeyCode::Up => -1,
+            KeyCode::Down => 1,
+            KeyCode::PageUp => -page,
+            _ => page,
+        };
+
+        let document = &self.buffer().document;
+        let position = &self.buffer().position;
+        let column = document.column_in_line(position, width);
+        let line = document.visual_line(position, width);
+        let line = document.step_lines(&line, lines, width);
+        self.buffer_mut().position = document.position_in_line(&line, column, width);
+    }
+
+    fn move_cursor(&mut self, key: Key) {
+        let Position {
+            x: mut position_x,
+            y: mut position_y,
+        } = self.buffer().position;
+
+        match key {
+            (_, KeyCode::Left) => {
+                if position_x > 0 {
+                    position_x -= 1;
+                } else if position_y > 0 {
+                    position_y -= 1;
+                    position_x = self
+                        .buffer()
+                        .document
+                        .width_at(&Position::at(0, position_y));
+                }
+            }
+            (_, KeyCode::Right) => {
+                if position_x < self.buffer().document.width_at(&self.buffer().position) {
                     position_x += 1;
-                } else {
-                    self.document.append_row();
-                    return self.process_key(key);
+                } else if position_y < self.buffer().document.height().saturating_sub(1) {
+                    position_y += 1;
+                    position_x = 0;
                 }
             }
+            (_, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown)
+                if self.config.wrap =>
+            {
+                return self.move_visual(key);
+            }
+            (_, KeyCode::Up) => {
+                position_y = position_y.saturating_sub(1);
+            }
+            (_, KeyCode::Down) => {
+                position_y += 1;
+            }
+            (_, KeyCode::Home) => {
+                position_x = 0;
+            }
+            (_, KeyCode::End) => {
+                position_x = self.buffer().document.width_at(&self.buffer().position);
+            }
+            (_, KeyCode::PageUp) => {
+                position_y = position_y.saturating_sub(self.window_height());
+            }
+            (_, KeyCode::PageDown) => {
+                position_y += self.window_height();
+            }
             _ => {}
         }
 
-        self.position = Position::at(position_x, position_y);
+        self.buffer_mut().position = Position::at(position_x, position_y);
     }
 
     fn sanitize_position(&mut self) {
-        let doc_height = self.document.height();
+        let doc_height = self.buffer().document.height();
         let Position {
             x: mut position_x,
             y: mut position_y,
-        } = self.position;
+        } = self.buffer().position;
 
         if position_y >= doc_height {
             position_y = doc_height.saturating_sub(1);
         }
 
-        let width = self.document.width_at(&self.position);
+        let width = self
+            .buffer()
+            .document
+            .width_at(&Position::at(0, position_y));
         if position_x > width {
             position_x = width;
         }
 
-        self.position = Position::at(position_x, position_y);
+        self.buffer_mut().position = Position::at(position_x, position_y);
     }
 
     fn scroll(&mut self) {
-        let window_width = self.window_width();
-        let window_height = self.window_height();
-
-        let Position {
-            x: position_x,
-            y: position_y,
-        } = self.position;
-        let Position {
-            x: mut offset_x,
-            y: mut offset_y,
-        } = self.offset;
+        self.buffer_mut().offset = follow(
+            &self.buffer().document,
+            &self.buffer().position,
+            self.buffer().offset,
+            self.window_width(),
+            self.window_height(),
+            self.config.wrap,
+        );
+    }
 
-        if position_x < offset_x {
-            offset_x = position_x;
-        }
-        if position_y < offset_y {
-            offset_y = position_y;
-        }
-        if position_x >= offset_x + window_width {
-            offset_x = position_x - window_width + 1;
-        }
-        if position_y >= offset_y + window_height {
-            offset_y = position_y - window_height + 1;
+    fn toggle_wrap(&mut self) {
+        self.config.wrap = !self.config.wrap;
+        // Offsets count columns or lines into a row depending on the mode.
+        self.buffer_mut().offset.x = 0;
+        for pane in &mut self.panes {
+            pane.offset.x = 0;
         }
-
-        self.offset = Position::at(offset_x, offset_y);
+        self.status_message = StatusMessage::wrap_toggled(self.config.wrap);
     }
 
     fn process_prompt(&mut self, key: Key) {
@@ -467,19 +1438,31 @@ impl<'a> Editor<'a> {
             (_, KeyCode::Esc) => self.mode = EditorMode::Insert,
             (_, KeyCode::Backspace) => {
                 self.prompt.pop();
+                if let EditorMode::Prompt(EditorPrompt::Search) = &self.mode {
+                    self.search_incremental();
+                }
             }
             (_, KeyCode::Enter) => {
-                if let EditorMode::Prompt(prompt) = &self.mode {
+                if let EditorMode::Prompt(prompt) = mem::replace(&mut self.mode, EditorMode::Insert)
+                {
                     match prompt {
                         EditorPrompt::Save => {
-                            self.document.filename = Some(self.prompt.clone());
+                            self.buffer_mut().document.filename = Some(self.prompt.clone());
                             self.save_document();
                         }
                         EditorPrompt::Search => self.search_incremental(),
+                        EditorPrompt::Replace => self.replace_with_prompt(),
+                        EditorPrompt::ReplaceWith { pattern } => self.replace_start(&pattern),
+                        EditorPrompt::Open => self.open_file(&self.prompt.clone()),
+                        EditorPrompt::SwitchBuffer => self.pick_buffer(&self.prompt.clone()),
+                        EditorPrompt::GotoLine => self.goto_line(&self.prompt.clone()),
+                        EditorPrompt::ReplaceConfirm { .. }
+                        | EditorPrompt::Recover { .. }
+                        | EditorPrompt::FileChanged
+                        | EditorPrompt::Window => {}
                     }
                 }
 
-                self.mode = EditorMode::Insert;
                 self.prompt = String::new();
             }
             (_, KeyCode::Left | KeyCode::Up) => {
@@ -492,6 +1475,19 @@ impl<'a> Editor<'a> {
                     self.search_next();
                 }
             }
+            (KeyModifiers::CONTROL, KeyCode::Char(c @ ('r' | 'e' | 'w'))) => {
+                if let EditorMode::Prompt(EditorPrompt::Search | EditorPrompt::Replace) = &self.mode
+                {
+                    match c {
+                        'r' => self.search_options.regex = !self.search_options.regex,
+                        'e' => self.search_options.case = self.search_options.case.next(),
+                        _ => self.search_options.whole_word = !self.search_options.whole_word,
+                    }
+                }
+                if let EditorMode::Prompt(EditorPrompt::Search) = &self.mode {
+                    self.search_incremental();
+                }
+            }
             (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => {
                 self.prompt.push(c);
                 if let EditorMode::Prompt(EditorPrompt::Search) = &self.mode {
@@ -503,20 +1499,375 @@ impl<'a> Editor<'a> {
     }
 
     fn window_width(&self) -> usize {
-        self.terminal.size().width as usize
+        self.text_rect(self.focus, self.pane_rect()).width
     }
 
     fn window_height(&self) -> usize {
-        self.terminal.size().height as usize - 2 // Last two lines is for status bar
+        self.pane_rect().height.saturating_sub(1) // Last line is for the status bar
+    }
+
+    /// The screen shared by panes, leaving out the message bar.
+    fn area(&self) -> Rect {
+        let size = self.terminal.size();
+        Rect {
+            x: 0,
+            y: 0,
+            width: size.width as usize,
+            height: (size.height as usize).saturating_sub(1),
+        }
+    }
+
+    fn pane_rects(&self) -> Vec<(usize, Rect)> {
+        self.layout.rects(self.area())
+    }
+
+    /// The part of `rect`, the area of `pane`, left for text next to the line numbers.
+    fn text_rect(&self, pane: usize, rect: Rect) -> Rect {
+        let document = if pane == self.focus {
+            &self.buffer().document
+        } else {
+            &self.buffers[self.panes[pane].buffer].document
+        };
+        let gutter_width = match self.config.line_numbers {
+            LineNumbers::Off => 0,
+            LineNumbers::Absolute | LineNumbers::Relative => {
+                document.height().max(1).to_string().len() + 1
+            }
+        };
+        // Always leave a column for the text.
+        let gutter_width = gutter_width.min(rect.width.saturating_sub(1));
+        Rect {
+            x: rect.x + gutter_width,
+            width: rect.width - gutter_width,
+            ..rect
+        }
+    }
+
+    /// The number shown in the gutter of `pane` next to `row`.
+    fn line_number(&self, pane: usize, row: usize) -> usize {
+        let cursor_row = if pane == self.focus {
+            self.buffer().position.y
+        } else {
+            self.panes[pane].position.y
+        };
+        match self.config.line_numbers {
+            LineNumbers::Relative if row != cursor_row => row.abs_diff(cursor_row),
+            _ => row + 1,
+        }
+    }
+
+    fn toggle_line_numbers(&mut self) {
+        self.config.line_numbers = match self.config.line_numbers {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+        };
+        self.status_message = StatusMessage::line_numbers(self.config.line_numbers);
+    }
+
+    fn pane_rect(&self) -> Rect {
+        self.pane_rects()
+            .into_iter()
+            .find(|(pane, _)| *pane == self.focus)
+            .map(|(_, rect)| rect)
+            .unwrap_or_default()
+    }
+
+    fn process_recover(&mut self, key: Key) {
+        let EditorMode::Prompt(EditorPrompt::Recover { swap }) =
+            mem::replace(&mut self.mode, EditorMode::Insert)
+        else {
+            return;
+        };
+
+        match key {
+            (_, KeyCode::Char('y')) => {
+                self.buffer_mut().document.restore(*swap);
+                self.status_message = StatusMessage::swap_recovered();
+            }
+            (_, KeyCode::Char('n') | KeyCode::Esc) => {
+                self.buffer().document.remove_swap();
+                self.status_message = StatusMessage::swap_discarded();
+            }
+            _ => self.mode = EditorMode::Prompt(EditorPrompt::Recover { swap }),
+        }
+    }
+
+    fn process_file_changed(&mut self, key: Key) {
+        self.mode = EditorMode::Insert;
+        match key {
+            (_, KeyCode::Char('r')) => self.reload_document(StatusMessage::file_reloaded()),
+            (_, KeyCode::Char('o')) => {
+                self.buffer_mut().disk_change_ignored = false;
+                self.status_message = match self.buffer_mut().document.overwrite() {
+                    Ok(()) => StatusMessage::save_file_ok(),
+                    Err(e) => StatusMessage::save_file_error(&e),
+                };
+            }
+            (_, KeyCode::Char('k') | KeyCode::Esc) => self.buffer_mut().disk_change_ignored = true,
+            _ => self.mode = EditorMode::Prompt(EditorPrompt::FileChanged),
+        }
+    }
+
+    /// Notices the file being changed by another program while idle. A document
+    /// without unsaved changes simply follows the file.
+    fn check_disk(&mut self) {
+        if !matches!(self.mode, EditorMode::Insert)
+            || self.buffer().disk_change_ignored
+            || !self.buffer_mut().document.changed_on_disk()
+        {
+            return;
+        }
+
+        if self.buffer().document.is_dirty() {
+            self.mode = EditorMode::Prompt(EditorPrompt::FileChanged);
+            self.status_message = StatusMessage::file_changed();
+        } else {
+            self.reload_document(StatusMessage::file_changed_reloaded());
+        }
+    }
+
+    fn reload_document(&mut self, reloaded: StatusMessage) {
+        let buffer = self.buffer_mut();
+        buffer.disk_change_ignored = false;
+        buffer.anchor = None;
+        self.status_message = match buffer.document.reload() {
+            Ok(()) => reloaded,
+            Err(e) => {
+                let filename = self.buffer().document.filename.clone().unwrap_or_default();
+                StatusMessage::open_file_error(&filename, &e)
+            }
+        };
+    }
+
+    fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// The buffer being edited.
+    fn buffer(&self) -> &Buffer {
+        &self.buffers[self.current]
+    }
+
+    fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.current]
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index >= self.buffer_count() {
+            return;
+        }
+
+        self.current = index;
+        self.panes[self.focus].buffer = index;
+        let message = self.buffer_mut().message.take();
+        self.status_message = StatusMessage::buffer_switched(
+            index,
+            self.buffer_count(),
+            self.buffer().name(),
+            message.as_deref(),
+        );
+        self.offer_recovery();
+    }
+
+    fn process_window(&mut self, key: Key) {
+        self.mode = EditorMode::Insert;
+        match key {
+            (_, KeyCode::Char('s')) => self.split_pane(Split::Horizontal),
+            (_, KeyCode::Char('v')) => self.split_pane(Split::Vertical),
+            (_, KeyCode::Char('w')) => self.focus_pane((self.focus + 1) % self.panes.len()),
+            (_, KeyCode::Char('c')) => self.close_pane(),
+            (_, KeyCode::Left | KeyCode::Char('h')) => self.move_focus(Direction::Left),
+            (_, KeyCode::Down | KeyCode::Char('j')) => self.move_focus(Direction::Down),
+            (_, KeyCode::Up | KeyCode::Char('k')) => self.move_focus(Direction::Up),
+            (_, KeyCode::Right | KeyCode::Char('l')) => self.move_focus(Direction::Right),
+            // Resizing tends to take a few steps, so stay ready for more.
+            (_, KeyCode::Char(c @ ('+' | '-' | '>' | '<'))) => {
+                let (split, delta) = match c {
+                    '+' => (Split::Horizontal, 1),
+                    '-' => (Split::Horizontal, -1),
+                    '>' => (Split::Vertical, 1),
+                    _ => (Split::Vertical, -1),
+                };
+                self.layout.resize(self.area(), self.focus, split, delta);
+                self.mode = EditorMode::Prompt(EditorPrompt::Window);
+            }
+            _ => {}
+        }
+    }
+
+    /// Splits the focused pane, giving the new half a view of the same buffer.
+    fn split_pane(&mut self, split: Split) {
+        let pane = self.panes.len();
+        self.panes.push(Pane {
+            buffer: self.current,
+            position: self.buffer().position,
+            offset: self.buffer().offset,
+        });
+        self.layout.split(self.focus, split, pane);
+        self.focus_pane(pane);
+    }
+
+    fn close_pane(&mut self) {
+        if !self.layout.close(self.focus) {
+            self.status_message = StatusMessage::last_pane();
+            return;
+        }
+        self.panes.remove(self.focus);
+        self.focus = self.focus.saturating_sub(1);
+        self.load_pane();
+    }
+
+    fn move_focus(&mut self, direction: Direction) {
+        if let Some(pane) = layout::neighbour(&self.pane_rects(), self.focus, direction) {
+            self.focus_pane(pane);
+        }
+    }
+
+    fn focus_pane(&mut self, pane: usize) {
+        if pane == self.focus {
+            return;
+        }
+        self.panes[self.focus].position = self.buffer().position;
+        self.panes[self.focus].offset = self.buffer().offset;
+        self.focus = pane;
+        self.load_pane();
+    }
+
+    /// Puts the view of the focused pane into its buffer, to be edited there.
+    fn load_pane(&mut self) {
+        let Pane {
+            buffer,
+            position,
+            offset,
+        } = self.panes[self.focus];
+        self.current = buffer;
+        let buffer = self.buffer_mut();
+        buffer.position = position;
+        buffer.offset = offset;
+        self.offer_recovery();
+    }
+
+    /// Asks whether to recover the current buffer from its swap file, if one was found.
+    fn offer_recovery(&mut self) {
+        if let Some(swap) = self.buffer_mut().recovery.take() {
+            self.status_message =
+                StatusMessage::swap_found(&swap::summary(&self.buffer().document, &swap));
+            self.mode = EditorMode::Prompt(EditorPrompt::Recover { swap });
+        }
+    }
+
+    fn goto_prompt(&mut self) {
+        self.mode = EditorMode::Prompt(EditorPrompt::GotoLine);
+        self.status_message = StatusMessage::help_goto();
+    }
+
+    fn goto_line(&mut self, input: &str) {
+        match goto_target(input, &self.buffer().position, &self.buffer().document) {
+            Ok(position) => {
+                let buffer = self.buffer_mut();
+                buffer.position = position;
+                buffer.anchor = None;
+                self.center();
+            }
+            Err(status_message) => self.status_message = status_message,
+        }
+    }
+
+    /// Scrolls so that the cursor is in the middle of the window.
+    fn center(&mut self) {
+        let (width, height) = (self.window_width(), self.window_height());
+        let position = self.buffer().position;
+        self.buffer_mut().offset = if self.config.wrap {
+            let document = &self.buffer().document;
+            let line = document.visual_line(&position, width);
+            document.step_lines(&line, lines_up(height / 2 + 1), width)
+        } else {
+            Position::at(0, position.y.saturating_sub(height / 2))
+        };
+        self.scroll();
+    }
+
+    fn open_prompt(&mut self) {
+        self.mode = EditorMode::Prompt(EditorPrompt::Open);
+        self.status_message = StatusMessage::help_open();
+    }
+
+    fn buffer_prompt(&mut self) {
+        self.mode = EditorMode::Prompt(EditorPrompt::SwitchBuffer);
+        let names: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(index, buffer)| {
+                let modified = if buffer.document.is_dirty() { " +" } else { "" };
+                format!("{}: {}{modified}", index + 1, buffer.name())
+            })
+            .collect();
+        self.status_message = StatusMessage::buffer_list(&names);
+    }
+
+    /// Switches to the buffer numbered `query`, or else the first whose name contains it.
+    fn pick_buffer(&mut self, query: &str) {
+        let index = query
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+            .filter(|&index| index < self.buffer_count())
+            .or_else(|| {
+                self.buffers
+                    .iter()
+                    .position(|buffer| buffer.name().contains(query))
+            });
+
+        match index {
+            Some(index) => self.switch_buffer(index),
+            None => self.status_message = StatusMessage::no_such_buffer(query),
+        }
+    }
+
+    /// Switches to the buffer of `filename`, opening it first if needed.
+    fn open_file(&mut self, filename: &str) {
+        if filename.is_empty() {
+            return;
+        }
+        let open = self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.document.filename.as_deref() == Some(filename));
+        if let Some(index) = open {
+            return self.switch_buffer(index);
+        }
+
+        match Self::open_document(self.config, filename) {
+            Ok((document, status_message)) => {
+                let mut buffer = Buffer::new(document);
+                buffer.message = status_message.map(|status_message| status_message.text);
+                self.buffers.push(buffer);
+                self.switch_buffer(self.buffer_count() - 1);
+            }
+            Err(status_message) => self.status_message = status_message,
+        }
+    }
+
+    fn write_swap(&mut self) {
+        for buffer in &mut self.buffers {
+            buffer.write_swap();
+        }
     }
 
     fn try_quit(&mut self) {
-        if self.document.is_dirty() {
+        let dirty = self
+            .buffers
+            .iter()
+            .filter(|buffer| buffer.document.is_dirty())
+            .count();
+        if dirty > 0 {
             if self.quit_dirty {
                 self.quit = true;
             } else {
                 self.quit_dirty = true;
-                self.status_message = StatusMessage::warn_dirty();
+                self.status_message = StatusMessage::warn_dirty(dirty);
             }
         } else {
             self.quit = true;
@@ -524,13 +1875,88 @@ impl<'a> Editor<'a> {
     }
 
     fn save_document(&mut self) {
-        match self.document.save() {
-            Ok(_) => self.status_message = StatusMessage::save_file_ok(),
+        // Saving the piped buffer only decides what goes down the pipeline.
+        if self.pipe == Some(self.current) {
+            self.status_message = match self.buffer().document.encode() {
+                Ok(piped) => {
+                    self.piped = Some(piped);
+                    self.buffer_mut().document.mark_saved();
+                    StatusMessage::save_pipe_ok()
+                }
+                Err(e) => StatusMessage::save_file_error(&e),
+            };
+            return;
+        }
+
+        match self.buffer_mut().document.save() {
+            Ok(()) => self.status_message = StatusMessage::save_file_ok(),
             Err(OperationError::EmptyFilename) => self.save_prompt(),
+            Err(OperationError::ChangedOnDisk) => {
+                self.mode = EditorMode::Prompt(EditorPrompt::FileChanged);
+                self.status_message = StatusMessage::file_changed();
+            }
             Err(e) => self.status_message = StatusMessage::save_file_error(&e),
         }
     }
 
+    fn toggle_line_ending(&mut self) {
+        let line_ending = self.buffer().document.line_ending().toggled();
+        self.status_message = match self.buffer_mut().document.set_line_ending(line_ending) {
+            Ok(()) => StatusMessage::line_ending_changed(line_ending),
+            Err(e) => StatusMessage::new(e.to_string()),
+        };
+    }
+
+    /// Deletes the selection before an edit made by `key`. Typing then goes on to
+    /// replace it, while deleting is done; returns whether nothing is left to do.
+    fn replace_selection(&mut self, key: Key) -> bool {
+        let edits = matches!(
+            key,
+            (
+                _,
+                KeyCode::Backspace | KeyCode::Delete | KeyCode::Enter | KeyCode::Tab
+            ) | (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(_))
+        );
+        edits
+            && !self.buffer().document.is_read_only()
+            && self.delete_selection()
+            && matches!(key.1, KeyCode::Backspace | KeyCode::Delete)
+    }
+
+    /// Deletes the selected text, if any, leaving the cursor where it started.
+    fn delete_selection(&mut self) -> bool {
+        let Some((from, to)) = self.buffer().selection() else {
+            return false;
+        };
+        let buffer = self.buffer_mut();
+        buffer.anchor = None;
+        if buffer.document.delete_range(&from, &to).is_err() {
+            return false;
+        }
+        buffer.position = from;
+        true
+    }
+
+    fn undo(&mut self) {
+        let buffer = self.buffer_mut();
+        buffer.anchor = None;
+        if let Some(position) = buffer.document.undo() {
+            buffer.position = position;
+        } else {
+            self.status_message = StatusMessage::nothing_to_undo();
+        }
+    }
+
+    fn redo(&mut self) {
+        let buffer = self.buffer_mut();
+        buffer.anchor = None;
+        if let Some(position) = buffer.document.redo() {
+            buffer.position = position;
+        } else {
+            self.status_message = StatusMessage::nothing_to_redo();
+        }
+    }
+
     fn save_prompt(&mut self) {
         self.mode = EditorMode::Prompt(EditorPrompt::Save);
         self.status_message = StatusMessage::help_save();
@@ -538,48 +1964,278 @@ impl<'a> Editor<'a> {
 
     fn search_prompt(&mut self) {
         self.mode = EditorMode::Prompt(EditorPrompt::Search);
+        let buffer = self.buffer_mut();
+        buffer.search_origin = buffer.position;
+        buffer.search_hit = None;
         self.status_message = StatusMessage::help_search();
     }
 
+    fn search_query(&mut self) -> Option<Query> {
+        match Query::new(&self.prompt, self.search_options) {
+            Ok(query) => Some(query),
+            Err(e) => {
+                let buffer = self.buffer_mut();
+                buffer.search_hit = None;
+                buffer.search_counts = MatchCounts::default();
+                self.status_message = StatusMessage::invalid_search_pattern(&e);
+                None
+            }
+        }
+    }
+
     fn search_incremental(&mut self) {
-        if let Some(hit) = self.document.search(&self.prompt, &Position::zero()) {
-            self.position = hit.position;
-            self.searched_hits = vec![hit];
-            self.status_message = StatusMessage::help_search();
+        self.buffer_mut().search_query = self.search_query();
+        let Some(query) = &self.buffer().search_query else {
+            return;
+        };
+
+        self.buffer_mut().search_counts = self.buffer().document.count_matches(query);
+        let origin = self.buffer().search_origin;
+        self.search_forward(&origin);
+    }
+
+    fn search_next(&mut self) {
+        let after = self
+            .buffer()
+            .search_hit
+            .map_or(self.buffer().position, |hit| {
+                hit.position.add(&Position::at(1, 0))
+            });
+        self.search_forward(&after);
+    }
+
+    fn search_previous(&mut self) {
+        let before = self
+            .buffer()
+            .search_hit
+            .map_or(self.buffer().position, |hit| hit.position);
+        self.search_backward(&before);
+    }
+
+    fn search_forward(&mut self, after: &Position) {
+        let Some(query) = &self.buffer().search_query else {
+            return;
+        };
+
+        let (hit, wrapped) = match self.buffer().document.search(query, after) {
+            Some(hit) => (Some(hit), false),
+            None => (
+                self.buffer().document.search(query, &Position::zero()),
+                true,
+            ),
+        };
+        self.jump_to_hit(hit, wrapped, StatusMessage::search_wrapped_to_top);
+    }
+
+    fn search_backward(&mut self, before: &Position) {
+        let Some(query) = &self.buffer().search_query else {
+            return;
+        };
+
+        let (hit, wrapped) = if let Some(hit) = self.buffer().document.rsearch(query, before) {
+            (Some(hit), false)
+        } else {
+            let end = Position::at(0, self.buffer().document.height());
+            (self.buffer().document.rsearch(query, &end), true)
+        };
+        self.jump_to_hit(hit, wrapped, StatusMessage::search_wrapped_to_bottom);
+    }
+
+    fn jump_to_hit(
+        &mut self,
+        hit: Option<Hit>,
+        wrapped: bool,
+        wrap_message: fn() -> StatusMessage,
+    ) {
+        self.buffer_mut().search_hit = hit;
+        if let Some(hit) = hit {
+            self.buffer_mut().position = hit.position;
+            self.status_message = if wrapped {
+                wrap_message()
+            } else {
+                StatusMessage::help_search()
+            };
         } else {
             self.status_message = StatusMessage::no_search_results();
         }
     }
 
-    fn search_next(&mut self) {
-        let last_position = *self
-            .searched_hits
-            .last()
-            .map_or(&Position::zero(), |hit| &hit.position);
+    fn replace_prompt(&mut self) {
+        if self.buffer().document.is_read_only() {
+            self.status_message = StatusMessage::read_only();
+            return;
+        }
+        self.mode = EditorMode::Prompt(EditorPrompt::Replace);
+        self.status_message = StatusMessage::help_replace();
+    }
 
-        if let Some(hit) = self
-            .document
-            .search(&self.prompt, &last_position.add(&Position::at(1, 0)))
-        {
-            self.position = hit.position;
-            self.searched_hits.push(hit);
-            self.status_message = StatusMessage::help_search();
+    fn replace_with_prompt(&mut self) {
+        if self.prompt.is_empty() {
+            return;
+        }
+
+        self.mode = EditorMode::Prompt(EditorPrompt::ReplaceWith {
+            pattern: self.prompt.clone(),
+        });
+        self.status_message = StatusMessage::help_replace_with();
+    }
+
+    fn replace_start(&mut self, pattern: &str) {
+        let query = match Query::new(pattern, self.search_options) {
+            Ok(query) => query,
+            Err(e) => {
+                self.status_message = StatusMessage::invalid_search_pattern(&e);
+                return;
+            }
+        };
+
+        let origin = self.buffer().position;
+        let mut wrapped = false;
+        if let Some(hit) = self.next_replace_hit(&query, &origin, &origin, &mut wrapped) {
+            let buffer = self.buffer_mut();
+            buffer.search_counts = buffer.document.count_matches(&query);
+            buffer.position = hit.position;
+            buffer.search_hit = Some(hit);
+            self.mode = EditorMode::Prompt(EditorPrompt::ReplaceConfirm {
+                query,
+                replacement: self.prompt.clone(),
+                replaced: 0,
+                origin,
+                wrapped,
+            });
+            self.status_message = StatusMessage::help_replace_confirm();
         } else {
-            self.status_message = StatusMessage::no_more_search_results();
+            self.status_message = StatusMessage::no_search_results();
         }
     }
 
-    fn search_previous(&mut self) {
-        self.searched_hits.pop();
-        if let Some(hit) = self.searched_hits.last() {
-            self.position = hit.position;
-            self.status_message = StatusMessage::help_search();
+    fn process_replace(&mut self, key: Key) {
+        let EditorMode::Prompt(EditorPrompt::ReplaceConfirm {
+            query,
+            replacement,
+            mut replaced,
+            mut origin,
+            mut wrapped,
+        }) = mem::replace(&mut self.mode, EditorMode::Insert)
+        else {
+            return;
+        };
+        let Some(hit) = self.buffer().search_hit else {
+            return;
+        };
+
+        let next = match key {
+            (_, KeyCode::Char('y')) => {
+                Some(self.replace_hit(&query, &hit, &replacement, &mut origin, &mut replaced))
+            }
+            (_, KeyCode::Char('n')) => Some(hit.position.add(&Position::at(1, 0))),
+            (_, KeyCode::Char('a')) => {
+                let mut after =
+                    self.replace_hit(&query, &hit, &replacement, &mut origin, &mut replaced);
+                while let Some(hit) = self.next_replace_hit(&query, &after, &origin, &mut wrapped) {
+                    after =
+                        self.replace_hit(&query, &hit, &replacement, &mut origin, &mut replaced);
+                }
+                None
+            }
+            (_, KeyCode::Char('q') | KeyCode::Esc) => None,
+            _ => {
+                self.mode = EditorMode::Prompt(EditorPrompt::ReplaceConfirm {
+                    query,
+                    replacement,
+                    replaced,
+                    origin,
+                    wrapped,
+                });
+                return;
+            }
+        };
+
+        let next =
+            next.and_then(|after| self.next_replace_hit(&query, &after, &origin, &mut wrapped));
+        if let Some(hit) = next {
+            let buffer = self.buffer_mut();
+            buffer.position = hit.position;
+            buffer.search_hit = Some(hit);
+            self.mode = EditorMode::Prompt(EditorPrompt::ReplaceConfirm {
+                query,
+                replacement,
+                replaced,
+                origin,
+                wrapped,
+            });
         } else {
-            self.status_message = StatusMessage::no_more_search_results();
+            let buffer = self.buffer_mut();
+            buffer.search_hit = None;
+            buffer.search_counts = MatchCounts::default();
+            self.status_message = StatusMessage::replaced(replaced);
         }
     }
 
+    /// The next hit of `query` to replace at or after `after`. Past the end of
+    /// the document, it goes on from the top, up to `origin`.
+    fn next_replace_hit(
+        &self,
+        query: &Query,
+        after: &Position,
+        origin: &Position,
+        wrapped: &mut bool,
+    ) -> Option<Hit> {
+        let document = &self.buffer().document;
+        let before_origin = |hit: &Hit| (hit.position.y, hit.position.x) < (origin.y, origin.x);
+        if *wrapped {
+            return document.search(query, after).filter(before_origin);
+        }
+
+        document.search(query, after).or_else(|| {
+            *wrapped = true;
+            document
+                .search(query, &Position::zero())
+                .filter(before_origin)
+        })
+    }
+
+    /// Replaces `hit`, a match of `query`, as an undo step of its own, keeping
+    /// `origin` at the same text and counting the replacement in `replaced`.
+    /// Returns the position to continue searching from.
+    fn replace_hit(
+        &mut self,
+        query: &Query,
+        hit: &Hit,
+        replacement: &str,
+        origin: &mut Position,
+        replaced: &mut usize,
+    ) -> Position {
+        let (from, to) = hit.highlight;
+        let document = &mut self.buffer_mut().document;
+        let text = document.expand(query, hit, replacement);
+        document.checkpoint(&from);
+        if document.replace(hit, &text).is_err() {
+            // Leave the hit as it is, as if it was skipped.
+            return from.add(&Position::at(1, 0));
+        }
+        *replaced += 1;
+        let len = text.graphemes(true).count();
+        document.checkpoint(&from.add(&Position::at(len, 0)));
+        // Only the row replaced in has its matches changed.
+        let count = document.count_row_matches(query, from.y);
+        self.buffer_mut().search_counts.update(from.y, count);
+
+        if from.y == origin.y && from.x < origin.x {
+            origin.x = (origin.x + len).saturating_sub(to.x - from.x);
+        }
+        // An empty match would be found again at the same place.
+        let skip = usize::from(from == to);
+        from.add(&Position::at(len + skip, 0))
+    }
+
     fn die(&mut self, e: &Error) {
+        // Leave the unsaved changes behind in swap files, to be recovered on the next launch.
+        for buffer in &mut self.buffers {
+            if buffer.document.is_dirty() {
+                let _ = buffer.document.write_swap();
+            }
+        }
         self.terminal.clear().unwrap(); // We cannot handle error here, already dying
         panic!("{}", e);
     }
@@ -588,21 +2244,33 @@ impl<'a> Editor<'a> {
 struct SearchHitHighlighter {
     row_index: usize,
     hits: Vec<Hit>,
+    current: Option<Hit>,
 }
 
 impl SearchHitHighlighter {
     const SEARCH_HIGHLIGHT_BG_COLOR: Color = Color::DarkYellow;
-    
-    fn new(row_index: usize, hits: Vec<Hit>) -> Self {
-        Self { row_index, hits }
+
+    const SEARCH_MATCH_BG_COLOR: Color = Color::Rgb {
+        r: 92,
+        g: 80,
+        b: 36,
+    };
+
+    fn new(row_index: usize, hits: Vec<Hit>, current: Option<Hit>) -> Self {
+        Self {
+            row_index,
+            hits,
+            current,
+        }
     }
 }
 
 impl Highlighter for SearchHitHighlighter {
-    fn highlight(&self, _line: &str) -> Vec<Highlight> {
+    fn highlight(&self, _line: &str, _start: usize) -> Vec<Highlight> {
         let mut highlights = Vec::new();
 
-        if let Some(hit) = self.hits.last() {
+        // The current hit goes first, so it wins over the other matches.
+        if let Some(hit) = self.current {
             if self.row_index == hit.highlight.0.y {
                 highlights.push(Highlight::new(
                     hit.highlight.0.x,
@@ -613,6 +2281,203 @@ impl Highlighter for SearchHitHighlighter {
             }
         }
 
+        for hit in &self.hits {
+            if self.row_index == hit.highlight.0.y {
+                highlights.push(Highlight::new(
+                    hit.highlight.0.x,
+                    hit.highlight.1.x,
+                    None,
+                    Some(Self::SEARCH_MATCH_BG_COLOR),
+                ));
+            }
+        }
+
         highlights
     }
 }
+
+struct SelectionHighlighter {
+    row_index: usize,
+    selection: (Position, Position),
+}
+
+impl SelectionHighlighter {
+    const SELECTION_BG_COLOR: Color = Color::Rgb {
+        r: 38,
+        g: 79,
+        b: 120,
+    };
+
+    fn new(row_index: usize, selection: (Position, Position)) -> Self {
+        Self {
+            row_index,
+            selection,
+        }
+    }
+}
+
+impl Highlighter for SelectionHighlighter {
+    fn highlight(&self, line: &str, line_start: usize) -> Vec<Highlight> {
+        let (start, end) = self.selection;
+        if self.row_index < start.y || self.row_index > end.y {
+            return vec![];
+        }
+
+        let from = if self.row_index == start.y {
+            start.x
+        } else {
+            0
+        };
+        let to = if self.row_index == end.y {
+            end.x
+        } else {
+            // The line break is selected too; show it as one more cell.
+            line_start + line.graphemes(true).count() + 1
+        };
+        vec![Highlight::new(
+            from,
+            to,
+            None,
+            Some(Self::SELECTION_BG_COLOR),
+        )]
+    }
+}
+
+/// Whether `position` is shown in a window of the given size scrolled to `offset`,
+/// with rows wrapped at `width` if `wrap` is set.
+fn in_view(
+    document: &Document,
+    position: &Position,
+    offset: &Position,
+    width: usize,
+    height: usize,
+    wrap: bool,
+) -> bool {
+    if !wrap {
+        return (offset.y..offset.y + height).contains(&position.y) && position.x >= offset.x;
+    }
+    let line = document.visual_line(position, width);
+    // Every row takes up at least one line, so far away rows need no counting.
+    (line.y, line.x) >= (offset.y, offset.x)
+        && line.y - offset.y < height
+        && document.lines_between(offset, &line, width) < height
+}
+
+/// `offset` moved just enough for `position` to be in view, like `scrolled`,
+/// but counting display columns, or visual lines if `wrap` is set.
+fn follow(
+    document: &Document,
+    position: &Position,
+    offset: Position,
+    width: usize,
+    height: usize,
+    wrap: bool,
+) -> Position {
+    if wrap {
+        let line = document.visual_line(position, width);
+        return if in_view(document, position, &offset, width, height, wrap) {
+            offset
+        } else if (line.y, line.x) < (offset.y, offset.x) {
+            line
+        } else {
+            document.step_lines(&line, lines_up(height), width)
+        };
+    }
+
+    let mut offset = scrolled(*position, offset, width, height);
+    // Tabs and wide characters take up more than one column each.
+    if let Some(row) = document.row(position.y) {
+        let column = row.to_raw_position(position.x);
+        if column >= width {
+            // The first grapheme starting less than `width` columns before the cursor.
+            let first = row.position_at_width(column - width) + 1;
+            offset.x = offset.x.max(first.min(position.x));
+        }
+    }
+    offset
+}
+
+/// Visual lines from the last line of a window of `height` lines up to its first.
+fn lines_up(height: usize) -> isize {
+    isize::try_from(height.saturating_sub(1)).map_or(isize::MIN, |lines| -lines)
+}
+
+/// Where the input of the go-to-line prompt leads from `from`: a line number,
+/// optionally followed by `:column`, a number of lines prefixed by `+` or `-`,
+/// or a percentage of the document prefixed by `%`. Numbers count from one.
+fn goto_target(
+    input: &str,
+    from: &Position,
+    document: &Document,
+) -> std::result::Result<Position, StatusMessage> {
+    let input = input.trim();
+    let (line, column) = match input.split_once(':') {
+        Some((line, column)) => (line, Some(column)),
+        None => (input, None),
+    };
+    let number = |text: &str| {
+        text.parse::<usize>()
+            .map_err(|_| StatusMessage::invalid_line(input))
+    };
+
+    let height = document.height();
+    let y = if let Some(percent) = line.strip_prefix('%') {
+        Some(number(percent)?)
+            .filter(|percent| *percent <= 100)
+            .map(|percent| height.saturating_sub(1) * percent / 100)
+    } else if let Some(lines) = line.strip_prefix('+') {
+        from.y.checked_add(number(lines)?)
+    } else if let Some(lines) = line.strip_prefix('-') {
+        from.y.checked_sub(number(lines)?)
+    } else {
+        number(line)?.checked_sub(1)
+    };
+    let y = y
+        .filter(|y| *y < height)
+        .ok_or_else(|| StatusMessage::line_out_of_range(line, height))?;
+
+    let width = document.width_at(&Position::at(0, y));
+    let x = match column {
+        Some(column) => number(column)?
+            .checked_sub(1)
+            .filter(|x| *x <= width)
+            .ok_or_else(|| StatusMessage::column_out_of_range(column, width + 1))?,
+        None => 0,
+    };
+    Ok(Position::at(x, y))
+}
+
+/// `offset` moved just enough for `position` to be within a window of the given size.
+fn scrolled(position: Position, offset: Position, width: usize, height: usize) -> Position {
+    let Position {
+        x: position_x,
+        y: position_y,
+    } = position;
+    let Position {
+        x: mut offset_x,
+        y: mut offset_y,
+    } = offset;
+
+    if position_x < offset_x {
+        offset_x = position_x;
+    }
+    if position_y < offset_y {
+        offset_y = position_y;
+    }
+    if position_x >= offset_x + width {
+        offset_x = position_x - width + 1;
+    }
+    if position_y >= offset_y + height {
+        offset_y = position_y - height + 1;
+    }
+
+    Position::at(offset_x, offset_y)
+}
+
+/// Cuts or pads `text` to exactly `width` columns.
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted: String = text.chars().take(width).collect();
+    let len = fitted.chars().count();
+    fitted.push_str(&" ".repeat(width - len));
+    fitted
+}
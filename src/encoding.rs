@@ -0,0 +1,204 @@
+use std::{
+    fmt::{Display, Write as _},
+    io::{self, Write},
+};
+
+/// How many leading bytes are looked at to tell text from binary content.
+const BINARY_SNIFF_LEN: usize = 8192;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16_BE_BOM: &[u8] = &[0xFE, 0xFF];
+const UTF32_LE_BOM: &[u8] = &[0xFF, 0xFE, 0x00, 0x00];
+const UTF32_BE_BOM: &[u8] = &[0x00, 0x00, 0xFE, 0xFF];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+    Latin1,
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Utf8 => write!(f, "UTF-8"),
+            Self::Utf8Bom => write!(f, "UTF-8 BOM"),
+            Self::Utf16Le => write!(f, "UTF-16LE"),
+            Self::Utf16Be => write!(f, "UTF-16BE"),
+            Self::Utf32Le => write!(f, "UTF-32LE"),
+            Self::Utf32Be => write!(f, "UTF-32BE"),
+            Self::Latin1 => write!(f, "Latin-1"),
+        }
+    }
+}
+
+impl Encoding {
+    /// Encoding of `bytes`, or `None` if they look like binary content.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(UTF8_BOM) {
+            return Some(Self::Utf8Bom);
+        }
+        // The UTF-32LE mark begins with the UTF-16LE one.
+        if bytes.starts_with(UTF32_LE_BOM) {
+            return Some(Self::Utf32Le);
+        }
+        if bytes.starts_with(UTF32_BE_BOM) {
+            return Some(Self::Utf32Be);
+        }
+        if bytes.starts_with(UTF16_LE_BOM) {
+            return Some(Self::Utf16Le);
+        }
+        if bytes.starts_with(UTF16_BE_BOM) {
+            return Some(Self::Utf16Be);
+        }
+        if bytes.iter().take(BINARY_SNIFF_LEN).any(|&b| b == 0) {
+            return None;
+        }
+
+        if std::str::from_utf8(bytes).is_ok() {
+            Some(Self::Utf8)
+        } else {
+            Some(Self::Latin1)
+        }
+    }
+
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 | Self::Latin1 => &[],
+            Self::Utf8Bom => UTF8_BOM,
+            Self::Utf16Le => UTF16_LE_BOM,
+            Self::Utf16Be => UTF16_BE_BOM,
+            Self::Utf32Le => UTF32_LE_BOM,
+            Self::Utf32Be => UTF32_BE_BOM,
+        }
+    }
+
+    /// Whether `bytes` end in the middle of a code unit, which decoding
+    /// replaces and saving would not write back.
+    pub fn is_truncated(self, bytes: &[u8]) -> bool {
+        let unit = match self {
+            Self::Utf16Le | Self::Utf16Be => 2,
+            Self::Utf32Le | Self::Utf32Be => 4,
+            Self::Utf8 | Self::Utf8Bom | Self::Latin1 => 1,
+        };
+        let bytes = bytes.get(self.bom().len()..).unwrap_or_default();
+        !bytes.chunks_exact(unit).remainder().is_empty()
+    }
+
+    /// Index of the first of `chars` which this encoding cannot represent.
+    pub fn unencodable(self, mut chars: impl Iterator<Item = char>) -> Option<usize> {
+        match self {
+            Self::Latin1 => chars.position(|c| u8::try_from(c).is_err()),
+            _ => None,
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let bytes = &bytes[self.bom().len()..];
+        match self {
+            Self::Utf8 | Self::Utf8Bom => String::from_utf8_lossy(bytes).into_owned(),
+            Self::Utf16Le => Self::decode_utf16(bytes, u16::from_le_bytes),
+            Self::Utf16Be => Self::decode_utf16(bytes, u16::from_be_bytes),
+            Self::Utf32Le => Self::decode_utf32(bytes, u32::from_le_bytes),
+            Self::Utf32Be => Self::decode_utf32(bytes, u32::from_be_bytes),
+            Self::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+        }
+    }
+
+    /// Writes `text` out in this encoding, without a byte order mark. Fails
+    /// on characters the encoding cannot represent; see `unencodable`.
+    pub fn encode(self, text: &str, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Utf8 | Self::Utf8Bom => out.write_all(text.as_bytes()),
+            Self::Utf16Le => {
+                let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                out.write_all(&bytes)
+            }
+            Self::Utf16Be => {
+                let bytes: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+                out.write_all(&bytes)
+            }
+            Self::Utf32Le => {
+                let bytes: Vec<u8> = text
+                    .chars()
+                    .flat_map(|c| u32::from(c).to_le_bytes())
+                    .collect();
+                out.write_all(&bytes)
+            }
+            Self::Utf32Be => {
+                let bytes: Vec<u8> = text
+                    .chars()
+                    .flat_map(|c| u32::from(c).to_be_bytes())
+                    .collect();
+                out.write_all(&bytes)
+            }
+            Self::Latin1 => {
+                let bytes = text
+                    .chars()
+                    .map(u8::try_from)
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                out.write_all(&bytes)
+            }
+        }
+    }
+
+    fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+        let pairs = bytes.chunks_exact(2);
+        let truncated = !pairs.remainder().is_empty();
+        let units = pairs.map(|pair| from_bytes([pair[0], pair[1]]));
+        let mut text: String = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        if truncated {
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+        text
+    }
+
+    fn decode_utf32(bytes: &[u8], from_bytes: fn([u8; 4]) -> u32) -> String {
+        let units = bytes.chunks_exact(4);
+        let truncated = !units.remainder().is_empty();
+        let mut text: String = units
+            .map(|unit| {
+                char::from_u32(from_bytes([unit[0], unit[1], unit[2], unit[3]]))
+                    .unwrap_or(char::REPLACEMENT_CHARACTER)
+            })
+            .collect();
+        if truncated {
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+        text
+    }
+}
+
+/// Renders `bytes` as a classic hex dump, sixteen bytes per line.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (index, line) in bytes.chunks(16).enumerate() {
+        let hex = line
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    char::from(b)
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        // Writing into a `String` cannot fail.
+        let _ = writeln!(dump, "{:08x}  {hex:<47}  |{ascii}|", index * 16);
+    }
+    dump
+}
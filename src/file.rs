@@ -0,0 +1,234 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File, Metadata, OpenOptions, Permissions},
+    hash::Hasher,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+    time::SystemTime,
+};
+
+/// How many names are tried for a temporary file before giving up.
+const TEMP_ATTEMPTS: u32 = 100;
+
+/// What a file looked like when it was last read or written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl Stamp {
+    pub fn new(metadata: &Metadata, content: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(content);
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: hasher.finish(),
+        }
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let content = fs::read(path)?;
+        Ok(Self::new(&metadata, &content))
+    }
+
+    /// Whether `metadata` may belong to a file other than the one stamped.
+    /// Only then is it worth reading the content to compare hashes.
+    pub fn is_stale(&self, metadata: &Metadata) -> bool {
+        self.modified != metadata.modified().ok() || self.len != metadata.len()
+    }
+
+    pub fn same_content(&self, other: &Stamp) -> bool {
+        self.len == other.len && self.hash == other.hash
+    }
+}
+
+/// Replaces the content of `path` with whatever `write` produces, without
+/// ever leaving a partially written file behind.
+///
+/// The content goes to a temporary file next to the target, which is synced
+/// and then renamed over it. Symlinks are written through, and the target's
+/// owner is kept. So are its permissions, unless `mode` gives others; on Unix,
+/// the file has them from the moment it is created. With `backup`, the
+/// previous content is kept as `path~`. Where no temporary file may be made
+/// because the directory is not writable, the target is overwritten in place
+/// instead; any other failure leaves it alone.
+pub fn write_atomic<F>(path: &Path, backup: bool, mode: Option<u32>, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = target
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file name"))?;
+    let metadata = fs::metadata(&target).ok();
+    let permissions = from_mode(mode).or_else(|| metadata.as_ref().map(Metadata::permissions));
+
+    let (temp, file) = match create_temp(dir, &name.to_string_lossy(), permissions.as_ref()) {
+        Ok(temp) => temp,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && metadata.is_some() => {
+            if backup {
+                fs::copy(&target, backup_path(&target))?;
+            }
+            return write_in_place(&target, permissions, write);
+        }
+        Err(e) => return Err(e),
+    };
+    let result = write_temp(file, metadata.as_ref(), permissions, write).and_then(|()| {
+        if backup && target.exists() {
+            fs::copy(&target, backup_path(&target))?;
+        }
+        fs::rename(&temp, &target)
+    });
+    if result.is_err() {
+        // The temporary file is useless now; the original is untouched.
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+
+    // Make the rename itself durable. Not every platform can sync a directory.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Creates a temporary file next to `name` in `dir`, under a name no one else uses.
+fn create_temp(
+    dir: &Path,
+    name: &str,
+    permissions: Option<&Permissions>,
+) -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    let mut attempts = 1;
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp = dir.join(format!(".{name}.{}.{count}.tmp", process::id()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        create_with(&mut options, permissions);
+        match options.open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            // Left behind by some earlier run; try the next name.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < TEMP_ATTEMPTS => {
+                attempts += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn write_temp<F>(
+    file: File,
+    metadata: Option<&Metadata>,
+    permissions: Option<Permissions>,
+    write: F,
+) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mut out = BufWriter::new(file);
+    write(&mut out)?;
+    out.flush()?;
+
+    let file = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+    // Changing the owner may clear some permissions, so it goes first.
+    if let Some(metadata) = metadata {
+        copy_owner(&file, metadata);
+    }
+    // Creating the file left out whatever the umask masks.
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.sync_all()
+}
+
+/// Truncates `path` and writes it anew, which leaves it half written if
+/// writing fails midway. `permissions`, if any, are set before writing.
+fn write_in_place<F>(path: &Path, permissions: Option<Permissions>, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let file = OpenOptions::new().write(true).truncate(true).open(path)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    let mut out = BufWriter::new(file);
+    write(&mut out)?;
+    out.flush()?;
+    out.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .sync_all()
+}
+
+/// Gives `file` the owner and group of the file described by `metadata`, as
+/// far as we are allowed to.
+#[cfg(unix)]
+fn copy_owner(file: &File, metadata: &Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    if fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+        // Only root may give a file away, but the group may still be ours.
+        let _ = fchown(file, None, Some(metadata.gid()));
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _metadata: &Metadata) {}
+
+/// Permission bits of the file at `path`.
+#[cfg(unix)]
+pub fn mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode(_path: &Path) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn from_mode(mode: Option<u32>) -> Option<Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+
+    mode.map(Permissions::from_mode)
+}
+
+/// Modes are a Unix notion; elsewhere files keep the target's permissions.
+#[cfg(not(unix))]
+fn from_mode(_mode: Option<u32>) -> Option<Permissions> {
+    None
+}
+
+/// Has `options` create files with `permissions`, less the umask.
+#[cfg(unix)]
+fn create_with(options: &mut OpenOptions, permissions: Option<&Permissions>) {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if let Some(permissions) = permissions {
+        options.mode(permissions.mode());
+    }
+}
+
+#[cfg(not(unix))]
+fn create_with(_options: &mut OpenOptions, _permissions: Option<&Permissions>) {}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push("~");
+    PathBuf::from(backup)
+}
@@ -27,7 +27,9 @@ impl Highlight {
 }
 
 pub trait Highlighter {
-    fn highlight(&self, line: &str) -> Vec<Highlight>;
+    /// Highlights for `line`, the graphemes of a row from grapheme `start`
+    /// on, placed by grapheme of the row.
+    fn highlight(&self, line: &str, start: usize) -> Vec<Highlight>;
 }
 
 pub struct CommonSyntaxHighlighter {
@@ -167,13 +169,13 @@ impl CommonSyntaxHighlighter {
 }
 
 impl Highlighter for CommonSyntaxHighlighter {
-    fn highlight(&self, line: &str) -> Vec<Highlight> {
+    fn highlight(&self, line: &str, line_start: usize) -> Vec<Highlight> {
         let mut highlights = vec![];
 
         for primary_keyword in &self.primary_keywords {
             let mut start = 0;
-            while let Some(index) = line[start..].find(format!("{} ", primary_keyword).as_str()) {
-                start = start + index;
+            while let Some(index) = line[start..].find(format!("{primary_keyword} ").as_str()) {
+                start += index;
                 let end = start + primary_keyword.len();
                 highlights.push(Highlight::new(
                     start,
@@ -187,8 +189,8 @@ impl Highlighter for CommonSyntaxHighlighter {
 
         for secondary_keyword in &self.secondary_keywords {
             let mut start = 0;
-            while let Some(index) = line[start..].find(format!("{} ", secondary_keyword).as_str()) {
-                start = start + index;
+            while let Some(index) = line[start..].find(format!("{secondary_keyword} ").as_str()) {
+                start += index;
                 let end = start + secondary_keyword.len();
                 highlights.push(Highlight::new(
                     start,
@@ -228,9 +230,7 @@ impl Highlighter for CommonSyntaxHighlighter {
                     is_number = is_number && Self::is_number(ch);
 
                     if ch == '"' || ch == '\'' {
-                        if !in_string {
-                            in_string = true;
-                        } else {
+                        if in_string {
                             highlights.push(Highlight::new(
                                 word_start,
                                 i + 1,
@@ -238,21 +238,27 @@ impl Highlighter for CommonSyntaxHighlighter {
                                 None,
                             ));
                             in_string = false;
+                        } else {
+                            in_string = true;
                         }
                     }
                 }
             }
         }
 
-        line.find("//").map(|pos| {
+        if let Some(pos) = line.find("//") {
             highlights.push(Highlight::new(
                 pos,
                 line.len(),
                 Some(Self::COMMENT_COLOR),
                 None,
-            ))
-        });
+            ));
+        }
 
+        for highlight in &mut highlights {
+            highlight.start += line_start;
+            highlight.end += line_start;
+        }
         highlights
     }
 }
@@ -0,0 +1,169 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::position::Position;
+
+/// A primitive mutation of a document, recorded so that it can be reverted.
+///
+/// Edits are placed by char index into the text rather than by position, so
+/// that reverting one restores the very same chars even where it joined or
+/// split graphemes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Inserts `text`, which may span several rows, at char index `at`.
+    Insert {
+        at: usize,
+        text: String,
+    },
+    Delete {
+        at: usize,
+        text: String,
+    },
+    /// Splits a row in two by inserting a line break at char index `at`.
+    Split {
+        at: usize,
+    },
+    /// Joins two rows by removing the line break at char index `at`.
+    Merge {
+        at: usize,
+    },
+    AppendRow,
+    RemoveLastRow,
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Self::Insert { at, text } => Self::Delete {
+                at: *at,
+                text: text.clone(),
+            },
+            Self::Delete { at, text } => Self::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+            Self::Split { at } => Self::Merge { at: *at },
+            Self::Merge { at } => Self::Split { at: *at },
+            Self::AppendRow => Self::RemoveLastRow,
+            Self::RemoveLastRow => Self::AppendRow,
+        }
+    }
+}
+
+/// A group of edits which are undone and redone together.
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub edits: Vec<Edit>,
+    pub before: Position,
+    pub after: Position,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Step>,
+    redo_stack: Vec<Step>,
+    cursor: Position,
+    open: bool,
+    // Char index at which the next typed char continues the run.
+    typing_at: Option<usize>,
+    // Number of steps on the undo stack when the document was last saved,
+    // `None` once that state can no longer be reached by undoing or redoing.
+    saved: Option<usize>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo_stack: vec![],
+            redo_stack: vec![],
+            cursor: Position::zero(),
+            open: false,
+            typing_at: None,
+            saved: Some(0),
+        }
+    }
+
+    /// Marks the boundary of an undo step. Edits recorded afterwards open a new
+    /// step which restores the cursor to `cursor`, except for a run of typed
+    /// characters, which keeps growing the current step.
+    pub fn checkpoint(&mut self, cursor: &Position) {
+        if self.open {
+            if let Some(step) = self.undo_stack.last_mut() {
+                step.after = *cursor;
+            }
+        }
+        self.cursor = *cursor;
+        self.open = false;
+    }
+
+    /// Adds `edit` to the current step. With `typed`, it continues a run of
+    /// typed characters; anything else, such as pasted text, is kept apart.
+    pub fn record(&mut self, edit: Edit, typed: bool) {
+        self.redo_stack.clear();
+        if self.saved > Some(self.undo_stack.len()) {
+            self.saved = None;
+        }
+
+        let typed = match &edit {
+            Edit::Insert { at, text } if typed && text.graphemes(true).count() == 1 => {
+                Some((*at, text.chars().count()))
+            }
+            _ => None,
+        };
+        let continues_typing = matches!(typed, Some((at, _)) if Some(at) == self.typing_at);
+        self.typing_at = typed.map(|(at, chars)| at + chars);
+
+        if !self.open && !continues_typing {
+            self.undo_stack.push(Step {
+                edits: vec![edit],
+                before: self.cursor,
+                after: self.cursor,
+            });
+        } else if let Some(step) = self.undo_stack.last_mut() {
+            step.edits.push(edit);
+        }
+        self.open = true;
+    }
+
+    /// The step which `undo` moves, if there is one.
+    pub fn undo_step(&self) -> Option<&Step> {
+        self.undo_stack.last()
+    }
+
+    /// The step which `redo` moves, if there is one.
+    pub fn redo_step(&self) -> Option<&Step> {
+        self.redo_stack.last()
+    }
+
+    /// Moves the last step over to the redo stack, once it has been reverted.
+    pub fn undo(&mut self) {
+        if let Some(step) = self.undo_stack.pop() {
+            self.seal();
+            self.redo_stack.push(step);
+        }
+    }
+
+    /// Moves the last undone step back to the undo stack, once it has been reapplied.
+    pub fn redo(&mut self) {
+        if let Some(step) = self.redo_stack.pop() {
+            self.seal();
+            self.undo_stack.push(step);
+        }
+    }
+
+    /// Remembers the current state as the saved one. Edits recorded afterwards
+    /// start a new step, so that undoing them leads back to it.
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo_stack.len());
+        self.seal();
+    }
+
+    /// Whether undoing and redoing led back to the state last saved.
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo_stack.len())
+    }
+
+    fn seal(&mut self) {
+        self.open = false;
+        self.typing_at = None;
+    }
+}
@@ -0,0 +1,274 @@
+use std::mem;
+
+use crate::position::Position;
+
+/// Smallest number of screen lines or columns a pane is shrunk to.
+const MIN_PANE_SIZE: usize = 2;
+/// Fractions of a split are kept in units of 1/`RATIO_SCALE`.
+const RATIO_SCALE: usize = 10_000;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    fn overlaps_horizontally(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width && other.x < self.x + self.width
+    }
+
+    fn overlaps_vertically(&self, other: &Rect) -> bool {
+        self.y < other.y + other.height && other.y < self.y + self.height
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// One pane above the other.
+    Horizontal,
+    /// Panes side by side, with a separator column between them.
+    Vertical,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// A view into a buffer. The pane with focus is tracked by the editor itself,
+/// so its entry is only up to date while it does not have focus.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Pane {
+    pub buffer: usize,
+    pub position: Position,
+    pub offset: Position,
+}
+
+/// How the screen is divided between panes, identified by their index.
+pub enum Layout {
+    Pane(usize),
+    Split {
+        split: Split,
+        // Part of the space given to `first`, out of `RATIO_SCALE`.
+        ratio: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Every pane with the area it takes up within `area`.
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            Self::Pane(pane) => vec![(*pane, area)],
+            Self::Split {
+                split,
+                ratio,
+                first,
+                second,
+            } => {
+                let (first_area, second_area, _) = Self::divide(area, *split, *ratio);
+                let mut rects = first.rects(first_area);
+                rects.append(&mut second.rects(second_area));
+                rects
+            }
+        }
+    }
+
+    /// The columns drawn between panes side by side.
+    pub fn separators(&self, area: Rect) -> Vec<Rect> {
+        match self {
+            Self::Pane(_) => vec![],
+            Self::Split {
+                split,
+                ratio,
+                first,
+                second,
+            } => {
+                let (first_area, second_area, separator) = Self::divide(area, *split, *ratio);
+                let mut separators: Vec<Rect> = separator.into_iter().collect();
+                separators.append(&mut first.separators(first_area));
+                separators.append(&mut second.separators(second_area));
+                separators
+            }
+        }
+    }
+
+    /// Divides the space of `pane` evenly between it and `new_pane`.
+    pub fn split(&mut self, pane: usize, split: Split, new_pane: usize) -> bool {
+        match self {
+            Self::Pane(index) if *index == pane => {
+                *self = Self::Split {
+                    split,
+                    ratio: RATIO_SCALE / 2,
+                    first: Box::new(Self::Pane(pane)),
+                    second: Box::new(Self::Pane(new_pane)),
+                };
+                true
+            }
+            Self::Pane(_) => false,
+            Self::Split { first, second, .. } => {
+                first.split(pane, split, new_pane) || second.split(pane, split, new_pane)
+            }
+        }
+    }
+
+    /// Gives the space of `pane` to its sibling. Panes after it move down by
+    /// one index, so that they keep matching a list the pane was removed from.
+    pub fn close(&mut self, pane: usize) -> bool {
+        if !self.remove(pane) {
+            return false;
+        }
+        self.renumber(pane);
+        true
+    }
+
+    fn remove(&mut self, pane: usize) -> bool {
+        let Self::Split { first, second, .. } = self else {
+            return false;
+        };
+
+        let sibling = match (first.as_ref(), second.as_ref()) {
+            (Self::Pane(index), _) if *index == pane => second,
+            (_, Self::Pane(index)) if *index == pane => first,
+            _ => return first.remove(pane) || second.remove(pane),
+        };
+        let sibling = mem::replace(sibling.as_mut(), Self::Pane(pane));
+        *self = sibling;
+        true
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Pane(index) if *index > removed => *index -= 1,
+            Self::Pane(_) => {}
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    /// Grows `pane` by `delta` lines or columns, depending on `split`, at the
+    /// expense of its neighbour in the closest split of that kind.
+    pub fn resize(&mut self, area: Rect, pane: usize, split: Split, delta: isize) -> bool {
+        let Self::Split {
+            split: kind,
+            ratio,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+
+        let (first_area, second_area, _) = Self::divide(area, *kind, *ratio);
+        let in_first = first.contains(pane);
+        let resized = if in_first {
+            first.resize(first_area, pane, split, delta)
+        } else if second.contains(pane) {
+            second.resize(second_area, pane, split, delta)
+        } else {
+            return false;
+        };
+        if resized || *kind != split {
+            return resized;
+        }
+
+        let (total, current) = match kind {
+            Split::Horizontal => (area.height, first_area.height),
+            Split::Vertical => (area.width.saturating_sub(1), first_area.width),
+        };
+        let delta = if in_first { delta } else { -delta };
+        let size = current.saturating_add_signed(delta).clamp(
+            MIN_PANE_SIZE,
+            total.saturating_sub(MIN_PANE_SIZE).max(MIN_PANE_SIZE),
+        );
+        // Round up, so that the size comes out the same when divided again.
+        *ratio = (size * RATIO_SCALE).div_ceil(total.max(1));
+        true
+    }
+
+    fn contains(&self, pane: usize) -> bool {
+        match self {
+            Self::Pane(index) => *index == pane,
+            Self::Split { first, second, .. } => first.contains(pane) || second.contains(pane),
+        }
+    }
+
+    /// The areas of both sides of a split, and of the separator between them.
+    fn divide(area: Rect, split: Split, ratio: usize) -> (Rect, Rect, Option<Rect>) {
+        let part = |total: usize| {
+            (total * ratio / RATIO_SCALE)
+                .max(MIN_PANE_SIZE)
+                .min(total.saturating_sub(MIN_PANE_SIZE))
+        };
+
+        match split {
+            Split::Horizontal => {
+                let height = part(area.height);
+                (
+                    Rect { height, ..area },
+                    Rect {
+                        y: area.y + height,
+                        height: area.height - height,
+                        ..area
+                    },
+                    None,
+                )
+            }
+            Split::Vertical => {
+                let available = area.width.saturating_sub(1);
+                let width = part(available);
+                (
+                    Rect { width, ..area },
+                    Rect {
+                        x: area.x + width + 1,
+                        width: available - width,
+                        ..area
+                    },
+                    Some(Rect {
+                        x: area.x + width,
+                        width: 1,
+                        ..area
+                    }),
+                )
+            }
+        }
+    }
+}
+
+/// The pane next to `from` in `direction`, preferring the closest one.
+pub fn neighbour(rects: &[(usize, Rect)], from: usize, direction: Direction) -> Option<usize> {
+    let (_, origin) = rects.iter().find(|(pane, _)| *pane == from)?;
+
+    rects
+        .iter()
+        .filter_map(|(pane, rect)| {
+            let distance = match direction {
+                Direction::Left if rect.overlaps_vertically(origin) => {
+                    origin.x.checked_sub(rect.x + rect.width)
+                }
+                Direction::Right if rect.overlaps_vertically(origin) => {
+                    rect.x.checked_sub(origin.x + origin.width)
+                }
+                Direction::Up if rect.overlaps_horizontally(origin) => {
+                    origin.y.checked_sub(rect.y + rect.height)
+                }
+                Direction::Down if rect.overlaps_horizontally(origin) => {
+                    rect.y.checked_sub(origin.y + origin.height)
+                }
+                _ => None,
+            }?;
+            let skew = rect.x.abs_diff(origin.x) + rect.y.abs_diff(origin.y);
+            Some(((distance, skew), *pane))
+        })
+        .min()
+        .map(|(_, pane)| pane)
+}
@@ -1,37 +1,84 @@
 #![warn(clippy::all, clippy::pedantic)]
+mod boundaries;
+mod buffer;
+mod cli;
+mod clipboard;
 mod color;
+mod config;
 mod document;
 mod editor;
+mod encoding;
+mod file;
 mod highlight;
+mod history;
+mod layout;
+mod line_ending;
 mod position;
 mod renderer;
 mod row;
+mod row_cache;
 mod search;
+mod swap;
 mod terminal;
 
-use std::{env, error::Error};
+use std::{
+    env,
+    error::Error,
+    io::{self, IsTerminal},
+    process,
+};
 
+use cli::{Command, FileArg};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use document::Document;
 use editor::Editor;
 use terminal::Terminal;
 
 type MainError = Box<dyn Error>;
 
 fn main() -> Result<(), MainError> {
-    enable_raw_mode()?;
+    let (config, files) = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Edit { config, files }) => (config, files),
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(Command::Version) => {
+            println!("hecto {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(message) => {
+            eprintln!("hecto: {message}\nTry 'hecto --help' for more information.");
+            process::exit(2);
+        }
+    };
 
-    let args: Vec<String> = env::args().collect();
-    let filename = if args.len() > 1 { Some(&args[1]) } else { None };
+    // Standard input is all read before the terminal is set up for editing.
+    let stdin = if files.iter().any(FileArg::is_stdin) {
+        if io::stdin().is_terminal() {
+            eprintln!("hecto: standard input is a terminal, not something to read from");
+            process::exit(2);
+        }
+        Some(Document::read(io::stdin().lock()))
+    } else {
+        None
+    };
+
+    enable_raw_mode()?;
 
     let mut terminal = Terminal::new()?;
-    let mut editor = if let Some(filename) = filename {
-        Editor::from_file(&mut terminal, filename)
+    let mut editor = if files.is_empty() {
+        Editor::new(&mut terminal, config)
     } else {
-        Editor::new(&mut terminal)
+        Editor::from_files(&mut terminal, config, &files, stdin)
     };
     editor.run();
 
     disable_raw_mode()?;
 
+    if config.pipe {
+        editor.write_output(&mut io::stdout().lock())?;
+    }
+
     Ok(())
 }
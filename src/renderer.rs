@@ -1,8 +1,8 @@
-use std::io;
+use std::{borrow::Cow, io};
 
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{color::Color, row::Row, highlight::Highlight};
+use crate::{color::Color, highlight::Highlight, row::Row};
 
 pub trait RenderOutput {
     fn style(content: &str, color: Option<Color>, background_color: Option<Color>) -> String;
@@ -25,21 +25,38 @@ pub trait RenderOutput {
 pub type Error = io::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Styles graphemes `start..end` of `row`, cut or padded to take up exactly
+/// `width` columns.
 pub fn render<Out>(
-    out: &mut Out,
     row: &Row,
-    range: (usize, usize),
+    start: usize,
+    end: usize,
+    width: usize,
     highlights: &[Highlight],
-) -> Result<()>
+) -> String
 where
     Out: RenderOutput,
 {
-    let (start, end) = range;
-
     let rendered = row.render(start, end);
 
-    let highlighted = rendered
+    // Tab stops are counted from the start of the row, not of the screen.
+    let mut column = row.to_raw_position(start);
+    let mut used = 0;
+    let mut highlighted = rendered
         .graphemes(true)
+        .map_while(|ch| {
+            let ch_width = row.width_at(ch, column);
+            if used + ch_width > width {
+                return None;
+            }
+            used += ch_width;
+            column += ch_width;
+            Some(if ch == "\t" {
+                Cow::Owned(" ".repeat(ch_width))
+            } else {
+                Cow::Borrowed(ch)
+            })
+        })
         .enumerate()
         .map(|(pos, ch)| {
             let mut color = None;
@@ -51,9 +68,10 @@ where
                     break;
                 }
             }
-            Out::style(ch, color, background_color)
+            Out::style(&ch, color, background_color)
         })
         .collect::<String>();
 
-    out.draw_line(format!("{highlighted}\r").as_str(), None, None)
+    highlighted.push_str(&" ".repeat(width - used));
+    highlighted
 }
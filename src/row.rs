@@ -1,34 +1,90 @@
-use std::cmp::{max, min};
+use std::{
+    borrow::Cow,
+    cmp::{max, min},
+    fmt::Display,
+    rc::Rc,
+};
 
-use unicode_segmentation::UnicodeSegmentation;
+use ropey::{iter::Chunks, RopeSlice};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
 use unicode_width::UnicodeWidthStr;
 
-pub struct Row {
-    string: String,
-    len: usize,
+use crate::{boundaries::Boundaries, search::Query};
+
+/// A read-only view of a single line of a document, without its line break.
+pub struct Row<'a> {
+    text: RopeSlice<'a>,
+    // `None` for rows where every char is a grapheme of its own.
+    boundaries: Option<Rc<Boundaries>>,
+    tab_width: usize,
 }
 
-impl From<&str> for Row {
-    fn from(str: &str) -> Self {
-        Self {
-            string: String::from(str),
-            len: str.graphemes(true).count(),
+impl Display for Row<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.text.chunks() {
+            write!(f, "{chunk}")?;
         }
+        Ok(())
     }
 }
 
-impl ToString for Row {
-    fn to_string(&self) -> String {
-        self.string.clone()
+impl<'a> Row<'a> {
+    pub fn new(text: RopeSlice<'a>, boundaries: Option<Rc<Boundaries>>, tab_width: usize) -> Self {
+        Self {
+            text,
+            boundaries,
+            tab_width,
+        }
+    }
+
+    /// Whether graphemes of `text` can be indexed without segmentation.
+    pub fn is_simple(text: RopeSlice) -> bool {
+        text.len_bytes() == text.len_chars()
+    }
+
+    pub fn grapheme_boundaries(text: RopeSlice) -> Boundaries {
+        let mut starts = vec![];
+        let mut offset = 0;
+        for grapheme in Graphemes::new(text, 0) {
+            starts.push(offset);
+            offset += grapheme.chars().count();
+        }
+        Boundaries::new(&starts, offset)
     }
-}
 
-impl Row {
-    pub fn new() -> Row {
-        Row {
-            string: String::new(),
-            len: 0,
+    /// Grapheme boundaries of `text` once `removed` chars at char offset `at`
+    /// were replaced by `inserted` ones, given the `boundaries` from before.
+    /// Only the graphemes around the edit are segmented again.
+    pub fn update_boundaries(
+        text: RopeSlice,
+        boundaries: &Boundaries,
+        at: usize,
+        removed: usize,
+        inserted: usize,
+    ) -> Boundaries {
+        // Whether there is a boundary before the edit only depends on the text before it.
+        let first = at
+            .checked_sub(1)
+            .map_or(0, |last| boundaries.position_of(last));
+        let mut offset = boundaries.char_at(first);
+        let mut starts = vec![];
+        let mut end = boundaries.len();
+
+        for grapheme in Graphemes::new(text, text.char_to_byte(offset)) {
+            starts.push(offset);
+            offset += grapheme.chars().count();
+            // Past the edit, the graphemes are the same again from the first
+            // boundary which was there before as well.
+            if offset >= at + inserted {
+                let old = offset - inserted + removed;
+                let pos = boundaries.position_of(old);
+                if boundaries.char_at(pos) == old {
+                    end = pos;
+                    break;
+                }
+            }
         }
+        boundaries.splice(first..end, &starts, text.len_chars())
     }
 
     pub fn render(&self, start: usize, end: usize) -> String {
@@ -36,87 +92,246 @@ impl Row {
         if start > end {
             return String::new();
         }
-        self.string
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect()
+        self.text
+            .slice(self.char_at(start)..self.char_at(end))
+            .to_string()
     }
 
     pub fn to_position(&self, raw_pos: usize) -> usize {
-        let mut pos = 0;
+        let char_pos = self.text.byte_to_char(min(raw_pos, self.text.len_bytes()));
+        match &self.boundaries {
+            Some(boundaries) => boundaries.position_of(char_pos),
+            None => char_pos,
+        }
+    }
+
+    pub fn to_raw_position(&self, pos: usize) -> usize {
+        self.graphemes().take(pos).fold(0, |column, grapheme| {
+            column + self.width_at(&grapheme, column)
+        })
+    }
+
+    /// Grapheme at display column `raw_pos`, or the end of the row past its last one.
+    pub fn position_at_width(&self, raw_pos: usize) -> usize {
         let mut width = 0;
-        for ch in self.string.graphemes(true) {
-            width += ch.len();
+        for (pos, grapheme) in self.graphemes().enumerate() {
+            width += self.width_at(&grapheme, width);
             if width > raw_pos {
-                break;
+                return pos;
             }
-            pos += 1;
         }
-        pos
+        self.len()
     }
 
-    pub fn to_raw_position(&self, pos: usize) -> usize {
-        self.string
-            .graphemes(true)
-            .take(pos)
-            .map(UnicodeWidthStr::width)
-            .sum()
+    /// Grapheme just past the last one from `start` on which fits within
+    /// `width` columns.
+    pub fn fit_width(&self, start: usize, width: usize) -> usize {
+        let mut column = self.to_raw_position(start);
+        let mut used = 0;
+        let graphemes = Graphemes::new(self.text, self.text.char_to_byte(self.char_at(start)));
+        for (pos, grapheme) in graphemes.enumerate() {
+            let grapheme_width = self.width_at(&grapheme, column);
+            if used + grapheme_width > width {
+                return start + pos;
+            }
+            used += grapheme_width;
+            column += grapheme_width;
+        }
+        self.len()
     }
 
-    pub fn insert_at(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-        } else {
-            let mut s = String::new();
-            for (i, ch) in self.string.graphemes(true).enumerate() {
-                if i == at {
-                    s.push(c);
+    /// Grapheme at which each visual line starts when the row is wrapped at
+    /// `width` columns, breaking after whitespace where there is some.
+    pub fn wrap(&self, width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        let mut column = 0;
+        let mut line_width = 0;
+        // Where the current visual line may be broken, and its width up to there.
+        let mut break_at = None;
+
+        for (pos, grapheme) in self.graphemes().enumerate() {
+            let grapheme_width = self.width_at(&grapheme, column);
+            while line_width + grapheme_width > width && starts.last() < Some(&pos) {
+                if let Some((start, break_width)) = break_at.take() {
+                    line_width -= break_width;
+                    starts.push(start);
+                } else {
+                    line_width = 0;
+                    starts.push(pos);
                 }
-                s.push_str(ch);
             }
-            self.string = s;
+
+            line_width += grapheme_width;
+            column += grapheme_width;
+            if grapheme.chars().all(char::is_whitespace) && pos + 1 < self.len() {
+                break_at = Some((pos + 1, line_width));
+            }
+        }
+        starts
+    }
+
+    /// Columns taken up by `grapheme` when shown at display column `column`.
+    /// A tab reaches up to the next tab stop.
+    pub fn width_at(&self, grapheme: &str, column: usize) -> usize {
+        if grapheme == "\t" {
+            self.tab_width - column % self.tab_width
+        } else {
+            grapheme.width()
         }
+    }
 
-        self.len += 1;
+    /// The graphemes of the row, in order.
+    fn graphemes(&self) -> Graphemes<'a> {
+        Graphemes::new(self.text, 0)
     }
 
-    pub fn delete_at(&mut self, at: usize) {
-        if at >= self.len() {
-            return;
+    /// Char offset within the row of the grapheme at `pos`.
+    pub fn char_at(&self, pos: usize) -> usize {
+        let pos = min(pos, self.len());
+        match &self.boundaries {
+            Some(boundaries) => boundaries.char_at(pos),
+            None => pos,
         }
+    }
 
-        let mut s = String::new();
-        for (i, ch) in self.string.graphemes(true).enumerate() {
-            if i != at {
-                s.push_str(ch);
-            }
+    /// Grapheme range of the first match of `query` at or after `after`.
+    pub fn search(&self, query: &Query, after: usize) -> Option<(usize, usize)> {
+        // Past the end, even an empty match would be the one at the end again.
+        if after > self.len() {
+            return None;
         }
-        self.string = s;
-        self.len -= 1;
+        let start = self.text.char_to_byte(self.char_at(after));
+
+        query
+            .find(self.text, start)
+            .map(|raw_range| self.to_range(raw_range))
     }
 
-    pub fn append(&mut self, row: &Row) {
-        self.string.push_str(&row.string);
-        self.len += row.len();
+    /// Grapheme range of the last match of `query` starting before `before`,
+    /// among those `search` steps through from the start of the row.
+    pub fn rsearch(&self, query: &Query, before: usize) -> Option<(usize, usize)> {
+        let mut last = None;
+        let mut after = 0;
+        while after < before.min(self.len() + 1) {
+            match self.search(query, after) {
+                Some(range) if range.0 < before => {
+                    last = Some(range);
+                    after = range.0 + 1;
+                }
+                _ => break,
+            }
+        }
+        last
     }
 
-    pub fn split_at(&mut self, at: usize) -> (Row, Row) {
-        let left = &self.string.graphemes(true).take(at).collect::<String>()[..];
-        let right = &self.string.graphemes(true).skip(at).collect::<String>()[..];
-        (Row::from(left), Row::from(right))
+    /// `replacement` for the match of `query` at grapheme `at`, with
+    /// references to its groups filled in.
+    pub fn expand(&self, query: &Query, at: usize, replacement: &str) -> String {
+        let start = self.text.char_to_byte(self.char_at(at));
+        query.expand(self.text, start, replacement)
     }
 
-    pub fn search(&self, query: &str, after: usize) -> Option<usize> {
-        let substr = &self.string.graphemes(true).skip(after).collect::<String>()[..];
+    /// Grapheme ranges of every match of `query` in the row, looking for the
+    /// next one a grapheme past the start of the last, as `search` is used.
+    pub fn search_all(&self, query: &Query) -> Vec<(usize, usize)> {
+        let mut ranges = vec![];
+        let mut after = 0;
+        while after <= self.len() {
+            let Some(range) = self.search(query, after) else {
+                break;
+            };
+            ranges.push(range);
+            after = range.0 + 1;
+        }
+        ranges
+    }
 
-        substr
-            .find(query)
-            .map(|raw_pos| self.to_position(raw_pos))
-            .map(|pos| pos + after)
+    /// Grapheme range covering the byte range `(raw_start, raw_end)`.
+    fn to_range(&self, (raw_start, raw_end): (usize, usize)) -> (usize, usize) {
+        let start = self.to_position(raw_start);
+        let end = if raw_end > raw_start {
+            self.to_position(raw_end - 1) + 1
+        } else {
+            start
+        };
+        (start, end)
     }
 
     pub fn len(&self) -> usize {
-        self.len
+        match &self.boundaries {
+            Some(boundaries) => boundaries.len(),
+            None => self.text.len_chars(),
+        }
+    }
+}
+
+/// The graphemes of a rope slice from a given boundary on, taken from its
+/// chunks as they are, unless a grapheme straddles two of them.
+pub struct Graphemes<'a> {
+    text: RopeSlice<'a>,
+    chunks: Chunks<'a>,
+    chunk: &'a str,
+    chunk_start: usize,
+    cursor: GraphemeCursor,
+}
+
+impl<'a> Graphemes<'a> {
+    /// Graphemes of `text` from byte `start`, which must be a grapheme boundary.
+    pub fn new(text: RopeSlice<'a>, start: usize) -> Self {
+        let (mut chunks, chunk_start, _, _) = text.chunks_at_byte(start);
+        let chunk = chunks.next().unwrap_or("");
+        Self {
+            text,
+            chunks,
+            chunk,
+            chunk_start,
+            cursor: GraphemeCursor::new(start, text.len_bytes(), true),
+        }
+    }
+
+    /// The grapheme at byte `start`, which goes on past the current chunk.
+    ///
+    /// It is segmented on its own rather than by handing the cursor the next
+    /// chunk, since the cursor counts a regional indicator right before a
+    /// chunk twice and would split a flag across two chunks.
+    fn straddling(&mut self, start: usize) -> Cow<'a, str> {
+        let mut text = self.chunk[start - self.chunk_start..].to_string();
+        loop {
+            self.chunk_start += self.chunk.len();
+            self.chunk = self.chunks.next().unwrap_or("");
+            text.push_str(self.chunk);
+
+            let mut cursor = GraphemeCursor::new(0, self.text.len_bytes() - start, true);
+            if let Ok(Some(end)) = cursor.next_boundary(&text, 0) {
+                self.cursor = GraphemeCursor::new(start + end, self.text.len_bytes(), true);
+                text.truncate(end);
+                return Cow::Owned(text);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.cursor.cur_cursor();
+        let end = loop {
+            match self.cursor.next_boundary(self.chunk, self.chunk_start) {
+                Ok(None) => return None,
+                Ok(Some(end)) => break end,
+                Err(GraphemeIncomplete::NextChunk) => return Some(self.straddling(start)),
+                Err(GraphemeIncomplete::PreContext(index)) => {
+                    let (chunk, chunk_start, _, _) = self.text.chunk_at_byte(index - 1);
+                    self.cursor.provide_context(chunk, chunk_start);
+                }
+                // Only `prev_boundary` and `is_boundary` ask for anything else.
+                Err(_) => unreachable!(),
+            }
+        };
+
+        Some(Cow::Borrowed(
+            &self.chunk[start - self.chunk_start..end - self.chunk_start],
+        ))
     }
 }
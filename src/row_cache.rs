@@ -0,0 +1,96 @@
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use crate::boundaries::Boundaries;
+
+/// Maximum number of rows whose graphemes and wrapping are kept around.
+const CAPACITY: usize = 1024;
+
+/// Grapheme boundaries and wrapped line starts of the rows used last, by row
+/// index. Once full, the row used least recently makes room for the next one.
+#[derive(Default)]
+pub struct RowCache {
+    rows: HashMap<usize, Entry>,
+    // Ticks on every lookup, to tell which row was used least recently.
+    clock: u64,
+}
+
+#[derive(Default)]
+struct Entry {
+    // Only rows with graphemes other than single chars have boundaries.
+    boundaries: Option<Rc<Boundaries>>,
+    // Line starts for the width they were wrapped at.
+    wrap: Option<(usize, Rc<[usize]>)>,
+    used: u64,
+}
+
+impl RowCache {
+    pub fn get(&mut self, index: usize) -> Option<Rc<Boundaries>> {
+        self.entry(index)?.boundaries.clone()
+    }
+
+    /// Line starts of row `index` when wrapped at `width` columns.
+    pub fn get_wrap(&mut self, index: usize, width: usize) -> Option<Rc<[usize]>> {
+        match &self.entry(index)?.wrap {
+            Some((wrapped, starts)) if *wrapped == width => Some(starts.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, boundaries: Rc<Boundaries>) {
+        self.entry_mut(index).boundaries = Some(boundaries);
+    }
+
+    pub fn insert_wrap(&mut self, index: usize, width: usize, starts: Rc<[usize]>) {
+        self.entry_mut(index).wrap = Some((width, starts));
+    }
+
+    /// Forgets everything about row `index`, whose content changed.
+    pub fn remove(&mut self, index: usize) {
+        self.rows.remove(&index);
+    }
+
+    /// Accounts for `rows` being replaced by `count` new rows, moving the
+    /// rows below along.
+    pub fn splice(&mut self, rows: Range<usize>, count: usize) {
+        let Range { start, end } = rows;
+        self.rows = std::mem::take(&mut self.rows)
+            .into_iter()
+            .filter(|(index, _)| !(start..end).contains(index))
+            .map(|(index, entry)| {
+                if index >= end {
+                    (index - (end - start) + count, entry)
+                } else {
+                    (index, entry)
+                }
+            })
+            .collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    fn entry(&mut self, index: usize) -> Option<&Entry> {
+        self.clock += 1;
+        let entry = self.rows.get_mut(&index)?;
+        entry.used = self.clock;
+        Some(entry)
+    }
+
+    fn entry_mut(&mut self, index: usize) -> &mut Entry {
+        if self.rows.len() >= CAPACITY && !self.rows.contains_key(&index) {
+            let oldest = self
+                .rows
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(&index, _)| index);
+            if let Some(oldest) = oldest {
+                self.rows.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let entry = self.rows.entry(index).or_default();
+        entry.used = self.clock;
+        entry
+    }
+}
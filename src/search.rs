@@ -1,5 +1,18 @@
+use std::{borrow::Cow, cell::RefCell, fmt::Display};
+
+use regex::{Regex, RegexBuilder};
+use regex_automata::{
+    hybrid::{self, LazyStateID},
+    util::{start, syntax},
+    Anchored,
+};
+use ropey::RopeSlice;
+
 use crate::position::Position;
 
+/// Number of matches past which counting them stops.
+const MATCH_COUNT_LIMIT: usize = 1000;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Hit {
     pub position: Position,
@@ -14,3 +27,301 @@ impl Hit {
         }
     }
 }
+
+/// Matches of a query counted row by row from the top, to tell where one of
+/// them stands among the others. Counting stops after `MATCH_COUNT_LIMIT`.
+#[derive(Default)]
+pub struct MatchCounts {
+    // Matches on each row counted so far.
+    rows: Vec<usize>,
+    total: usize,
+    complete: bool,
+}
+
+impl MatchCounts {
+    /// Counts from the number of matches on each row, in order.
+    pub fn new(rows: impl Iterator<Item = usize>) -> Self {
+        let mut rows = rows.peekable();
+        let mut counts = Self::default();
+        while counts.total < MATCH_COUNT_LIMIT {
+            let Some(count) = rows.next() else {
+                break;
+            };
+            counts.rows.push(count);
+            counts.total += count;
+        }
+        counts.complete = rows.peek().is_none();
+        counts
+    }
+
+    /// Takes a new number of matches on row `y` into account.
+    pub fn update(&mut self, y: usize, count: usize) {
+        if let Some(row) = self.rows.get_mut(y) {
+            self.total = self.total - *row + count;
+            *row = count;
+        }
+    }
+
+    /// "match N of M" for the match which comes after `before` others on row `y`.
+    pub fn describe(&self, y: usize, before: usize) -> String {
+        let more = if self.complete { "" } else { "+" };
+        if y >= self.rows.len() {
+            return format!("match ? of {}{more}", self.total);
+        }
+        let index: usize = self.rows[..y].iter().sum::<usize>() + before;
+        format!("match {} of {}{more}", index + 1, self.total)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Case {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// Insensitive, unless the pattern contains an uppercase letter.
+    Smart,
+}
+
+impl Case {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Sensitive => Self::Insensitive,
+            Self::Insensitive => Self::Smart,
+            Self::Smart => Self::Sensitive,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub case: Case,
+    pub whole_word: bool,
+}
+
+impl Display for SearchOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut flags = vec![];
+        if self.regex {
+            flags.push("regex");
+        }
+        match self.case {
+            Case::Sensitive => {}
+            Case::Insensitive => flags.push("ignore case"),
+            Case::Smart => flags.push("smart case"),
+        }
+        if self.whole_word {
+            flags.push("whole word");
+        }
+        write!(f, "{}", flags.join(", "))
+    }
+}
+
+pub struct Query {
+    regex: Regex,
+    // Whether the pattern is a regex, whose groups replacements refer to.
+    is_regex: bool,
+    // `None` if the pattern could not be built as lazy DFAs.
+    chunked: Option<Box<LazyDfas>>,
+}
+
+/// A pattern as lazy DFAs, which can be run over a rope chunk by chunk.
+struct LazyDfas {
+    regex: hybrid::regex::Regex,
+    cache: RefCell<hybrid::regex::Cache>,
+}
+
+/// The lazy DFAs could not be built, or met a byte they cannot handle.
+struct GaveUp;
+
+impl Query {
+    pub fn new(pattern: &str, options: SearchOptions) -> Result<Self, regex::Error> {
+        let case_insensitive = match options.case {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => !Self::has_uppercase(pattern, options.regex),
+        };
+
+        let mut escaped = if options.regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+        if options.whole_word {
+            // A literal only needs a word boundary on the ends that are part of a word.
+            let is_word = |ch: Option<char>| ch.is_some_and(|ch| ch.is_alphanumeric() || ch == '_');
+            let start = options.regex || is_word(pattern.chars().next());
+            let end = options.regex || is_word(pattern.chars().last());
+            escaped = format!(
+                "{}(?:{escaped}){}",
+                if start { r"\b" } else { "" },
+                if end { r"\b" } else { "" },
+            );
+        }
+
+        let regex = RegexBuilder::new(&escaped)
+            .case_insensitive(case_insensitive)
+            .build()?;
+        let chunked = hybrid::regex::Regex::builder()
+            .syntax(syntax::Config::new().case_insensitive(case_insensitive))
+            // Word boundaries work as long as the text around them is ASCII.
+            .dfa(hybrid::dfa::Config::new().unicode_word_boundary(true))
+            .build(&escaped)
+            .ok()
+            .map(|regex| {
+                let cache = RefCell::new(regex.create_cache());
+                Box::new(LazyDfas { regex, cache })
+            });
+
+        Ok(Self {
+            regex,
+            is_regex: options.regex,
+            chunked,
+        })
+    }
+
+    fn has_uppercase(pattern: &str, regex: bool) -> bool {
+        let mut escaped = false;
+        pattern.chars().any(|ch| {
+            // Escapes such as `\W` or `\S` in a regex are classes, not letters.
+            let is_letter = !escaped && ch.is_uppercase();
+            escaped = regex && !escaped && ch == '\\';
+            is_letter
+        })
+    }
+
+    /// Byte range of the first match in `text` starting at or after `start`.
+    pub fn find(&self, text: RopeSlice, start: usize) -> Option<(usize, usize)> {
+        if let Ok(found) = self.find_chunked(text, start) {
+            return found;
+        }
+
+        // The lazy DFAs give up on some text, such as non-ASCII letters next
+        // to a word boundary; only then is the text copied to search it whole.
+        let haystack: Cow<str> = text.into();
+        self.regex
+            .find_at(&haystack, start)
+            .map(|found| (found.start(), found.end()))
+    }
+
+    /// `replacement` for the match in `text` at byte `start`, with references
+    /// to the groups of a regex such as `$1` or `${name}` filled in.
+    pub fn expand(&self, text: RopeSlice, start: usize, replacement: &str) -> String {
+        if !self.is_regex {
+            return replacement.to_string();
+        }
+
+        let haystack: Cow<str> = text.into();
+        let mut expanded = String::new();
+        match self.regex.captures_at(&haystack, start) {
+            Some(captures) => captures.expand(replacement, &mut expanded),
+            None => expanded.push_str(replacement),
+        }
+        expanded
+    }
+
+    /// Like `find`, going over the chunks of `text` with the lazy DFAs.
+    fn find_chunked(
+        &self,
+        text: RopeSlice,
+        start: usize,
+    ) -> Result<Option<(usize, usize)>, GaveUp> {
+        let LazyDfas { regex, cache } = self.chunked.as_deref().ok_or(GaveUp)?;
+        let mut cache = cache.borrow_mut();
+        let (forward_cache, reverse_cache) = cache.as_parts_mut();
+
+        // The forward DFA finds where the leftmost-first match ends...
+        let forward = regex.forward();
+        let config = start::Config::new()
+            .anchored(Anchored::No)
+            .look_behind(start.checked_sub(1).map(|at| text.byte(at)));
+        let mut state = forward
+            .start_state(forward_cache, &config)
+            .map_err(|_| GaveUp)?;
+        let mut end = None;
+        let mut dead = false;
+        for (at, byte) in (start..).zip(text.bytes_at(start)) {
+            state = forward
+                .next_state(forward_cache, state, byte)
+                .map_err(|_| GaveUp)?;
+            // A match shows one byte late, once the byte after it is seen.
+            match Step::of(state)? {
+                Step::Match => end = Some(at),
+                Step::Dead => {
+                    dead = true;
+                    break;
+                }
+                Step::Going => {}
+            }
+        }
+        if !dead {
+            state = forward
+                .next_eoi_state(forward_cache, state)
+                .map_err(|_| GaveUp)?;
+            if state.is_match() {
+                end = Some(text.len_bytes());
+            }
+        }
+        let Some(end) = end else {
+            return Ok(None);
+        };
+
+        // ...and the reverse DFA, going back from there, where it starts.
+        let reverse = regex.reverse();
+        let config = start::Config::new()
+            .anchored(Anchored::Yes)
+            .look_behind(text.get_byte(end));
+        let mut state = reverse
+            .start_state(reverse_cache, &config)
+            .map_err(|_| GaveUp)?;
+        let mut found = None;
+        let mut dead = false;
+        for (at, byte) in (start..end).rev().zip(text.bytes_at(end).reversed()) {
+            state = reverse
+                .next_state(reverse_cache, state, byte)
+                .map_err(|_| GaveUp)?;
+            match Step::of(state)? {
+                Step::Match => found = Some(at + 1),
+                Step::Dead => {
+                    dead = true;
+                    break;
+                }
+                Step::Going => {}
+            }
+        }
+        if !dead {
+            state = match start.checked_sub(1) {
+                Some(before) => reverse.next_state(reverse_cache, state, text.byte(before)),
+                None => reverse.next_eoi_state(reverse_cache, state),
+            }
+            .map_err(|_| GaveUp)?;
+            if matches!(Step::of(state)?, Step::Match) {
+                found = Some(start);
+            }
+        }
+        Ok(found.map(|start| (start, end)))
+    }
+}
+
+/// Where a lazy DFA stands after a step.
+enum Step {
+    Match,
+    Dead,
+    Going,
+}
+
+impl Step {
+    fn of(state: LazyStateID) -> Result<Self, GaveUp> {
+        if !state.is_tagged() {
+            Ok(Self::Going)
+        } else if state.is_match() {
+            Ok(Self::Match)
+        } else if state.is_dead() {
+            Ok(Self::Dead)
+        } else if state.is_quit() {
+            Err(GaveUp)
+        } else {
+            Ok(Self::Going)
+        }
+    }
+}
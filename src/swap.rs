@@ -0,0 +1,61 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{document::Document, file};
+
+/// Mode of a swap file for a file which does not exist yet.
+const PRIVATE_MODE: u32 = 0o600;
+
+/// Path of the swap file kept for `filename`: `.name.swp` next to it.
+pub fn path(filename: &str) -> PathBuf {
+    let path = Path::new(filename);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.swp"))
+}
+
+/// Mode of the swap file for `filename`: that of the file itself, so it is
+/// readable by no one who could not read the file.
+pub fn mode(filename: &str) -> u32 {
+    file::mode(Path::new(filename)).map_or(PRIVATE_MODE, |mode| mode & 0o777)
+}
+
+/// Whether a swap file for `filename` was written after the file itself.
+pub fn is_newer(filename: &str) -> bool {
+    let Ok(swapped) = fs::metadata(path(filename)).and_then(|m| m.modified()) else {
+        return false;
+    };
+
+    fs::metadata(filename)
+        .and_then(|m| m.modified())
+        .map_or(true, |saved| swapped > saved)
+}
+
+/// Describes in one line how `recovered` differs from `original`.
+pub fn summary(original: &Document, recovered: &Document) -> String {
+    let line = |document: &Document, index: usize| document.row(index).map(|row| row.to_string());
+
+    let (old_len, new_len) = (original.height(), recovered.height());
+    let common = old_len.min(new_len);
+    let prefix = (0..common)
+        .take_while(|&i| line(original, i) == line(recovered, i))
+        .count();
+    let suffix = (0..common - prefix)
+        .take_while(|&i| line(original, old_len - 1 - i) == line(recovered, new_len - 1 - i))
+        .count();
+
+    if prefix == common && old_len == new_len {
+        return format!("swap file has the same {new_len} lines as the file");
+    }
+
+    let changed = new_len - prefix - suffix;
+    let removed = old_len - prefix - suffix;
+    format!(
+        "swap file differs from line {}: {changed} lines in place of {removed} ({new_len} lines total)",
+        prefix + 1
+    )
+}
@@ -1,14 +1,23 @@
-use std::io::{self, Stdout, Write};
+use std::{
+    fs::OpenOptions,
+    io::{self, IsTerminal, LineWriter, Write},
+    time::Duration,
+};
 
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
-    event::{read, Event, KeyCode as CrossTermKeyCode, KeyModifiers as CrossTermKeyModifiers},
+    event::{
+        poll, read, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste,
+        EnableMouseCapture, Event, KeyCode as CrossTermKeyCode,
+        KeyModifiers as CrossTermKeyModifiers, MouseButton as CrossTermMouseButton,
+        MouseEvent as CrossTermMouseEvent, MouseEventKind as CrossTermMouseEventKind,
+    },
     execute,
     style::{SetBackgroundColor, SetForegroundColor, Stylize},
     terminal::{size, Clear, ClearType},
 };
 
-use crate::position::Position;
+use crate::{clipboard, position::Position};
 use crate::{color::Color, renderer::RenderOutput};
 
 pub struct Size {
@@ -17,7 +26,8 @@ pub struct Size {
 }
 
 pub struct Terminal {
-    stdout: Stdout,
+    // Standard output, unless it goes down a pipe; the terminal itself then.
+    stdout: Box<dyn Write>,
     size: Size,
     cursor_position: Position,
 }
@@ -28,12 +38,32 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub type KeyModifiers = CrossTermKeyModifiers;
 pub type KeyCode = CrossTermKeyCode;
 pub type Key = (KeyModifiers, KeyCode);
+pub type MouseEvent = CrossTermMouseEvent;
+pub type MouseEventKind = CrossTermMouseEventKind;
+pub type MouseButton = CrossTermMouseButton;
+
+pub enum Input {
+    Key(Key),
+    Mouse(MouseEvent),
+    /// Text pasted all at once, with bracketed paste.
+    Paste(String),
+    /// The terminal changed size; `Terminal::size` is up to date already.
+    Resize,
+}
 
 impl Terminal {
     pub fn new() -> Result<Self> {
         let (width, height) = size()?;
+        let mut stdout: Box<dyn Write> = if io::stdout().is_terminal() {
+            Box::new(io::stdout())
+        } else {
+            Box::new(LineWriter::new(
+                OpenOptions::new().write(true).open("/dev/tty")?,
+            ))
+        };
+        execute!(stdout, EnableMouseCapture, EnableBracketedPaste)?;
         Ok(Self {
-            stdout: io::stdout(),
+            stdout,
             size: Size { width, height },
             cursor_position: Position::zero(),
         })
@@ -57,12 +87,27 @@ impl Terminal {
         execute!(self.stdout, MoveTo(position.x as u16, position.y as u16))
     }
 
-    pub fn read_key() -> Result<Key> {
-        loop {
-            if let Event::Key(event) = read()? {
-                return Ok((event.modifiers, event.code));
+    /// Waits up to `timeout` for input, returning `None` if there was none.
+    pub fn poll_input(&mut self, timeout: Duration) -> Result<Option<Input>> {
+        while poll(timeout)? {
+            match read()? {
+                Event::Key(event) => return Ok(Some(Input::Key((event.modifiers, event.code)))),
+                Event::Mouse(event) => return Ok(Some(Input::Mouse(event))),
+                Event::Paste(text) => return Ok(Some(Input::Paste(text))),
+                Event::Resize(width, height) => {
+                    self.size = Size { width, height };
+                    return Ok(Some(Input::Resize));
+                }
+                _ => {}
             }
         }
+        Ok(None)
+    }
+
+    /// Puts `text` on the system clipboard, if the terminal supports OSC 52.
+    pub fn set_clipboard(&mut self, text: &str) -> Result<()> {
+        write!(self.stdout, "{}", clipboard::osc52(text))?;
+        self.stdout.flush()
     }
 
     pub fn clear(&mut self) -> Result<()> {
@@ -74,6 +119,13 @@ impl Terminal {
     }
 }
 
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Nothing more can be done if the terminal is gone already.
+        let _ = execute!(self.stdout, DisableBracketedPaste, DisableMouseCapture);
+    }
+}
+
 impl RenderOutput for Terminal {
     fn draw(&mut self, content: &str, color: Option<Color>, bg_color: Option<Color>) -> Result<()> {
         if let Some(color) = color {
@@ -95,17 +147,13 @@ impl RenderOutput for Terminal {
         color: Option<Color>,
         bg_color: Option<Color>,
     ) -> Result<()> {
-        let is_last_line = self.cursor_position.y == self.size.height as usize - 1;
+        let is_last_line = self.cursor_position.y + 1 >= self.size.height as usize;
         let newline = if is_last_line { "" } else { "\n" };
 
         self.draw(&format!("{line}{newline}"), color, bg_color)
     }
 
-    fn style(
-        content: &str,
-        color: Option<Color>,
-        background_color: Option<Color>,
-    ) -> String {
+    fn style(content: &str, color: Option<Color>, background_color: Option<Color>) -> String {
         let mut styled = content.to_string();
         if let Some(color) = color {
             styled = styled.with(color).to_string();